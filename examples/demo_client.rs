@@ -1,38 +1,46 @@
 extern crate lines_of_action;
 
-use lines_of_action::api::{API,Move,Colour};
+use lines_of_action::api::{API,Colour,Turn,Error};
+use std::process;
+
+fn wait_on_opponent(api: &mut API) {
+    println!("Waiting");
+    loop {
+        match api.get_move().expect("Couldn't get move") {
+            Turn::GameOver(result) => {
+                println!("Game over, {}", result);
+                process::exit(0);
+            },
+            Turn::DrawOffered => api.decline_draw().expect("Couldn't decline draw"),
+            _ => break,
+        }
+    }
+    println!("Got");
+}
 
 fn main() {
-    let mut api = API::new(("127.0.0.1", API::default_server_port()));
-    api.register("Robot");
-    let (colour, opp) = api.wait_on_start();
+    let mut api = API::new(("127.0.0.1", API::default_server_port()))
+        .expect("Couldn't connect to server");
+    api.register("Robot").expect("Couldn't register");
+    let (colour, opp) = api.wait_on_start().expect("Couldn't start game");
     println!("{} is getting smashed.",opp);
 
+    //Black moves first
     if colour == Colour::White {
-        loop {
-            println!("Waiting");
-            api.get_move();
-            println!("Got");
-            let mov = Move::new(0,1,1,1);
-            api.move_piece(&mov);
-            println!("Waiting");
-            api.get_move();
-            println!("Got");
-            let mov = Move::new(1,1,0,1);
-            api.move_piece(&mov);
-        }
-    } else {
-        loop {
-            let mov = Move::new(1,7,1,6);
-            api.move_piece(&mov);
-            println!("Waiting");
-            api.get_move();
-            println!("Got");       
-            let mov = Move::new(1,6,1,7);
-            api.move_piece(&mov);
-            println!("Waiting");
-            api.get_move();
-            println!("Got");
+        wait_on_opponent(&mut api);
+    }
+
+    loop {
+        let mov = match api.legal_moves().expect("Couldn't get legal moves").first() {
+            Some(mov) => mov.clone(),
+            None => { api.resign().expect("Couldn't resign"); return; },
+        };
+
+        match api.move_piece(&mov) {
+            Ok(()) => {},
+            Err(Error::GameOver(result)) => { println!("Game over, {}", result); return; },
+            Err(e) => panic!("Couldn't move: {}", e),
         }
+        wait_on_opponent(&mut api);
     }
 }
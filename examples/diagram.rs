@@ -0,0 +1,64 @@
+extern crate lines_of_action;
+
+use std::env;
+use std::path::Path;
+use std::process;
+
+use lines_of_action::board::Board;
+use lines_of_action::command::Move;
+use lines_of_action::diagram::Diagram;
+use lines_of_action::record::GameRecord;
+
+//Draws a position to an SVG or PNG file. That's either a position given in full, with an
+//arrow for each move given, or the position in a recorded game after a number of moves (by
+//default all of them), with an arrow for the last one.
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.len() < 2 {
+        eprintln!("Usage: diagram FILE.svg|FILE.png POSITION [SX,SY,DX,DY...]");
+        eprintln!("       diagram FILE.svg|FILE.png RECORD [MOVES]");
+        process::exit(2);
+    }
+
+    //Anything which isn't a file is taken to be a position
+    let mut arrows = Vec::new();
+    let board = if Path::new(&args[1]).is_file() {
+        let record = GameRecord::load(Path::new(&args[1])).unwrap_or_else(|e| fail(&e));
+        let moves = match args.get(2) {
+            Some(n) => n.parse::<usize>().unwrap_or_else(|_| fail(&format!("Bad number of moves: {}", n)))
+                .min(record.moves.len()),
+            None => record.moves.len(),
+        };
+        if moves > 0 {
+            arrows.push(record.moves[moves - 1].clone());
+        }
+        record.positions().swap_remove(moves)
+    } else {
+        let board = args[1].parse::<Board>()
+            .unwrap_or_else(|()| fail(&format!("Bad position, and no record called that: {}", args[1])));
+        for arg in &args[2..] {
+            let n = arg.split(',').map(|n| n.parse::<u8>()).collect::<Result<Vec<_>,_>>();
+            match n {
+                Ok(ref n) if n.len() == 4 => arrows.push(Move::new(n[0], n[1], n[2], n[3])),
+                _ => fail(&format!("Bad move: {}", arg)),
+            }
+        }
+        board
+    };
+
+    let mut diagram = Diagram::new(board);
+    for mov in arrows {
+        diagram.highlights.push((mov.sx, mov.sy));
+        diagram.highlights.push((mov.dx, mov.dy));
+        diagram.arrows.push(mov);
+    }
+
+    if let Err(e) = diagram.save(Path::new(&args[0])) {
+        fail(&format!("Couldn't write {}: {}", args[0], e));
+    }
+}
+
+fn fail(why: &str) -> ! {
+    eprintln!("{}", why);
+    process::exit(1);
+}
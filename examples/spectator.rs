@@ -0,0 +1,17 @@
+extern crate lines_of_action;
+
+use lines_of_action::api::{API,Event};
+
+fn main() {
+    let mut api = API::new(("127.0.0.1", API::default_server_port()))
+        .expect("Couldn't connect to server");
+    api.register("Spectator").expect("Couldn't register");
+
+    loop {
+        match api.next_event().expect("Couldn't get event") {
+            Event::Position(board) => println!("Starting from {}", board.position()),
+            Event::Move(mov) => println!("({},{}) -> ({},{})", mov.sx, mov.sy, mov.dx, mov.dy),
+            Event::GameOver(result) => { println!("Game over, {}", result); break; },
+        }
+    }
+}
@@ -0,0 +1,29 @@
+extern crate lines_of_action;
+extern crate tungstenite;
+
+use lines_of_action::server::DEFAULT_WEBSOCKET_PORT;
+use tungstenite::Message;
+
+//Talks to the server over a WebSocket the way a browser would, printing what it says
+fn main() {
+    let url = format!("ws://127.0.0.1:{}", DEFAULT_WEBSOCKET_PORT);
+    let (mut socket, _) = tungstenite::connect(url.as_str()).expect("Couldn't connect to server");
+
+    let requests = [
+        r#"{"type":"hello","version":1}"#,
+        r#"{"type":"get_board"}"#,
+        r#"{"type":"legal_moves"}"#,
+    ];
+
+    for request in requests.iter() {
+        println!("> {}", request);
+        socket.send(Message::Text(request.to_string())).expect("Couldn't send request");
+
+        match socket.read().expect("Couldn't read reply") {
+            Message::Text(reply) => println!("< {}", reply.trim_end()),
+            reply => println!("< {:?}", reply),
+        }
+    }
+
+    socket.close(None).expect("Couldn't close connection");
+}
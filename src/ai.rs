@@ -0,0 +1,178 @@
+//! Computer players, which play through `api::API` like any other client
+
+use std::net::ToSocketAddrs;
+use std::time::{SystemTime,UNIX_EPOCH};
+use std::fmt::{Formatter,Display};
+use std::fmt;
+use std::str::FromStr;
+use super::api::{self,API,Board,Colour,Move,Turn,GameResult,GameStatus,Error};
+
+///How a computer player picks its moves
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Strategy {
+    ///The first legal move found
+    First,
+    ///Any legal move
+    Random,
+    ///A winning move if there is one, otherwise the move which best draws its pieces together
+    Greedy,
+}
+
+impl Display for Strategy {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Strategy::First => "first",
+            Strategy::Random => "random",
+            Strategy::Greedy => "greedy",
+        })
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Strategy, ()> {
+        match s {
+            "first" => Ok(Strategy::First),
+            "random" => Ok(Strategy::Random),
+            "greedy" => Ok(Strategy::Greedy),
+            _ => Err(()),
+        }
+    }
+}
+
+//How spread out a side's pieces are: the mean distance of each from their centre, counting
+//diagonal steps as one
+fn spread(board: &Board, colour: Colour) -> f64 {
+    let mut pieces = Vec::new();
+    for x in 0..8 {
+        for y in 0..8 {
+            let cell = board.cells[x][y];
+            if cell.has_piece && cell.colour == colour {
+                pieces.push((x as f64, y as f64));
+            }
+        }
+    }
+    if pieces.is_empty() {
+        return 0.0;
+    }
+
+    let n = pieces.len() as f64;
+    let cx = pieces.iter().map(|&(x,_)| x).sum::<f64>() / n;
+    let cy = pieces.iter().map(|&(_,y)| y).sum::<f64>() / n;
+    pieces.iter().map(|&(x,y)| (x - cx).abs().max((y - cy).abs())).sum::<f64>() / n
+}
+
+///A computer player
+pub struct Ai {
+    strategy: Strategy,
+    //State for picking random moves
+    seed: u64,
+}
+
+impl Ai {
+    pub fn new(strategy: Strategy) -> Ai {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ai {
+            strategy: strategy,
+            //The generator gets stuck on 0
+            seed: (now.as_secs() ^ now.subsec_nanos() as u64) | 1,
+        }
+    }
+
+    //xorshift64
+    fn next_random(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+
+    ///Picks a move for `colour`, or `None` if they can't move
+    pub fn choose_move(&mut self, board: &Board, colour: Colour) -> Option<Move> {
+        let moves = board.legal_moves(colour);
+        if moves.is_empty() {
+            return None;
+        }
+
+        match self.strategy {
+            Strategy::First => Some(moves[0].clone()),
+            Strategy::Random => {
+                let i = (self.next_random() % moves.len() as u64) as usize;
+                Some(moves[i].clone())
+            },
+            Strategy::Greedy => {
+                let mut best = None;
+                let mut best_score = ::std::f64::NEG_INFINITY;
+                for mov in moves {
+                    let mut after = board.clone();
+                    if after.move_piece(&mov, &colour).is_err() {
+                        continue;
+                    }
+
+                    let score = match after.winner(colour) {
+                        Some(winner) if winner == colour => return Some(mov),
+                        //Giving the game away is worse than anything else
+                        Some(_) => ::std::f64::MIN,
+                        None => -spread(&after, colour),
+                    };
+                    if best.is_none() || score > best_score {
+                        best = Some(mov);
+                        best_score = score;
+                    }
+                }
+                best
+            },
+        }
+    }
+}
+
+//Waits on the opponent's move, declining any draws. Returns the result if the game ends.
+fn wait_on_opponent(api: &mut API) -> api::Result<Option<GameResult>> {
+    loop {
+        match api.get_move()? {
+            Turn::Move(_) => return Ok(None),
+            Turn::GameOver(result) => return Ok(Some(result)),
+            Turn::DrawOffered => api.decline_draw()?,
+            Turn::DrawDeclined => {},
+        }
+    }
+}
+
+///Plays a whole game as `name` against whoever else joins the server at `addr`, and returns
+///the result
+pub fn play<A: ToSocketAddrs>(addr: A, name: &str, strategy: Strategy) -> api::Result<GameResult> {
+    let mut api = API::new(addr)?;
+    let mut ai = Ai::new(strategy);
+    api.register(name)?;
+    let (colour, opponent) = api.wait_on_start()?;
+    debug!("{} is playing {:?} against {}", name, colour, opponent);
+
+    //Black moves first
+    if colour == Colour::White {
+        if let Some(result) = wait_on_opponent(&mut api)? {
+            return Ok(result);
+        }
+    }
+
+    loop {
+        let state = api.get_board()?;
+        if let GameStatus::Over(result) = state.status {
+            return Ok(result);
+        }
+
+        let mov = match ai.choose_move(&state.board, colour) {
+            Some(mov) => mov,
+            None => return api.resign(),
+        };
+        match api.move_piece(&mov) {
+            Ok(()) => {},
+            Err(Error::GameOver(result)) => return Ok(result),
+            Err(e) => return Err(e),
+        }
+
+        if let Some(result) = wait_on_opponent(&mut api)? {
+            return Ok(result);
+        }
+    }
+}
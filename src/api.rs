@@ -1,103 +1,463 @@
 extern crate regex;
 
-use self::regex::{Regex,Captures};
-use std::net::{TcpStream, SocketAddr, ToSocketAddrs};
-use std::io::{Read,Write};
-use super::server::PORT;
+use self::regex::Regex;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::collections::VecDeque;
+use std::io::{self,Read,Write};
+use std::fmt::{self,Formatter,Display};
+use std::error;
+use std::result;
+use super::server::DEFAULT_PORT;
+use super::command::{CommandData,Reply};
+use super::json;
 
-pub use super::command::Move;
-pub use super::board::Colour;
+pub use super::command::{Move,Event,GameResult,WinReason,GameStatus,BoardState,CommandErr,
+                         Handshake,Capability,PROTOCOL_VERSION};
+pub use super::board::{Board,Colour};
+
+///What happened while waiting on the opponent
+#[derive(Clone,Debug,PartialEq)]
+pub enum Turn {
+    ///The opponent moved
+    Move(Move),
+    ///The opponent offered a draw, which should be accepted or declined
+    DrawOffered,
+    ///The opponent declined your offer of a draw
+    DrawDeclined,
+    GameOver(GameResult),
+}
+
+///Something went wrong talking to the server
+#[derive(Debug)]
+pub enum Error {
+    ///Couldn't talk to the server, including when it closes the connection
+    Io(io::Error),
+    ///The server said something we didn't understand
+    Protocol(String),
+    ///The server refused to do what was asked, e.g. because a move was illegal
+    Rejected(CommandErr),
+    ///The request couldn't be carried out because the game has ended
+    GameOver(GameResult),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::Protocol(ref msg) => write!(f, "Unexpected reply from server: {}", msg),
+            Error::Rejected(ref why) => write!(f, "Server refused: {}", why),
+            Error::GameOver(result) => write!(f, "Game over, {}", result),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+//Which flavour of the protocol to speak
+#[derive(Clone,Copy,Debug,PartialEq)]
+enum Mode {
+    Text,
+    Json,
+}
+
+fn format_move(mov: &Move) -> String {
+    format!("({},{}) -> ({},{})", mov.sx,mov.sy,mov.dx,mov.dy)
+}
+
+fn format_text_request(request: &CommandData) -> String {
+    match *request {
+        CommandData::Register(ref name,_) =>
+            format!("Hello, my name is {}, you killed my father, prepare to die;", name),
+        CommandData::Ready(_) => "Bring it, yo;".to_string(),
+        CommandData::Move(_,ref mov) => format!("{};", format_move(mov)),
+        CommandData::GetMove(_) => "Gimmeh!;".to_string(),
+        CommandData::Message(_,ref msg) => format!("\"{}\";", msg),
+        CommandData::Resign(_) => "I give up;".to_string(),
+        CommandData::OfferDraw(_) => "Fancy a draw?;".to_string(),
+        CommandData::AcceptDraw(_) => "Aye, go on then;".to_string(),
+        CommandData::DeclineDraw(_) => "Not a chance;".to_string(),
+        CommandData::GetBoard(_) => "What's the state of play?;".to_string(),
+        CommandData::GetLegalMoves(_,None) => "Where can I go?;".to_string(),
+        CommandData::GetLegalMoves(_,Some((x,y))) => format!("Where can ({},{}) go?;", x, y),
+        CommandData::Hello(_,version) => format!("Wotcha, I speak version {};", version),
+        CommandData::Leave(_) => panic!("Leaving is done by closing the connection"),
+        CommandData::Shutdown => panic!("Only the server can shut itself down"),
+    }
+}
+
+fn parse_text_move (caps: regex::Captures) -> Move {
+    Move::new_vec(caps.iter()
+                  .skip(1)
+                  .map(|x:Option<&str>| { x.unwrap().parse::<u8>().unwrap() })
+                  .collect::<Vec<_>>())
+}
+
+fn parse_text_reply (msg: &str) -> Option<Reply> {
+    let move_re = Regex::new(r"^\((\d),(\d)\) ?-> ?\((\d),(\d)\);").ok().expect("Bad regex");
+    let moves_re = Regex::new(r"\((\d),(\d)\)->\((\d),(\d)\)").ok().expect("Bad regex");
+    let game_over_re = Regex::new(r"^Game over, (.+);").ok().expect("Bad regex");
+    let start_re = Regex::new(r"^You are ([A-Za-z]+) and ([A-Za-z0-9]+) wants to batter you;")
+        .ok().expect("Bad regex");
+    let state_re = Regex::new(r"^The state of play: ([wb./]+), ([A-Za-z]+) to move, move (\d+), (.+);")
+        .ok().expect("Bad regex");
+    let position_re = Regex::new(r"^Here's the board: ([wb./]+);").ok().expect("Bad regex");
+    let hello_re = Regex::new(r"^Aye, we'll speak version (\d+) and I can do (.*);").ok().expect("Bad regex");
+
+    if let Some(caps) = move_re.captures(msg) {
+        Some(Reply::Move(parse_text_move(caps)))
+    } else if let Some(caps) = game_over_re.captures(msg) {
+        caps.at(1).unwrap().parse::<GameResult>().ok().map(Reply::GameOver)
+    } else if msg.starts_with("Oh, hai ") {
+        Some(Reply::Registered)
+    } else if let Some(caps) = start_re.captures(msg) {
+        caps.at(1).unwrap().parse::<Colour>().ok()
+            .map(|colour| Reply::Start(colour, caps.at(2).unwrap().to_string()))
+    } else if let Some(caps) = state_re.captures(msg) {
+        let status = match caps.at(4).unwrap() {
+            "waiting for players" => GameStatus::Waiting,
+            "in progress" => GameStatus::InProgress,
+            s => GameStatus::Over(s.trim_start_matches("game over, ").parse::<GameResult>().ok()?),
+        };
+
+        Some(Reply::Board(BoardState {
+            board: caps.at(1).unwrap().parse::<Board>().ok()?,
+            turn: caps.at(2).unwrap().parse::<Colour>().ok()?,
+            move_number: caps.at(3).unwrap().parse::<u32>().ok()?,
+            status: status,
+        }))
+    } else if msg.starts_with("Your options are: ") {
+        Some(Reply::Moves(moves_re.captures_iter(msg).map(parse_text_move).collect()))
+    } else if let Some(caps) = hello_re.captures(msg) {
+        //Capabilities from newer servers are ignored
+        let capabilities = caps.at(2).unwrap().split(", ")
+            .filter_map(|c| c.parse::<Capability>().ok())
+            .collect();
+        Some(Reply::Hello(Handshake {
+            version: caps.at(1).unwrap().parse::<u32>().ok()?,
+            capabilities: capabilities,
+        }))
+    } else if let Some(caps) = position_re.captures(msg) {
+        caps.at(1).unwrap().parse::<Board>().ok().map(|board| Reply::Event(Event::Position(board)))
+    } else {
+        match msg {
+            "Move successful;" | "I'll ask them;" | "Suit yourself;" => Some(Reply::Ok),
+            "They fancy a draw;" => Some(Reply::DrawOffered),
+            "They don't fancy a draw;" => Some(Reply::DrawDeclined),
+            _ => msg.trim_end_matches(';').parse::<CommandErr>().ok().map(Reply::Rejected),
+        }
+    }
+}
 
 ///API for communicating with the lines of action server.
 ///
 ///The server can be communicated with directly using telnet. The protocol is documented [here](../server/index.html#protocol)
 pub struct API {
     connection: TcpStream,
+    mode: Mode,
+    //Events which arrived while waiting for a reply, for `next_event`
+    events: VecDeque<Event>,
 }
 
 impl API {
-    fn read_message (stream: &mut TcpStream) -> String {
+    fn read_message (&mut self) -> Result<String> {
+        let delimiter = match self.mode {
+            Mode::Text => b';',
+            Mode::Json => b'\n',
+        };
         let mut c = [0;1];
         let mut buf = Vec::new();
 
-        stream.read(&mut c);
-        while c[0] != ';' as u8 {
+        loop {
+            if self.connection.read(&mut c)? == 0 {
+                return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                    "Server closed the connection")));
+            }
+
+            //Skip the whitespace between messages
+            if buf.is_empty() && (c[0] as char).is_whitespace() {
+                continue;
+            }
+
             buf.push(c[0]);
-            stream.read(&mut c);
+            if c[0] == delimiter {
+                break;
+            }
         }
-        buf.push(c[0]);
-        stream.read(&mut c);
 
-        String::from_utf8(buf).unwrap()
+        String::from_utf8(buf)
+            .map_err(|e| Error::Protocol(String::from_utf8_lossy(e.as_bytes()).into_owned()))
     }
-    
-    ///Connect to the server at the given socket address
-    pub fn new<A: ToSocketAddrs> (addr: A) -> API {
-        let connection = TcpStream::connect(addr).unwrap();
 
-        API {
+    fn read_reply (&mut self) -> Result<Reply> {
+        let msg = self.read_message()?;
+        let reply = match self.mode {
+            Mode::Text => parse_text_reply(&msg),
+            Mode::Json => json::parse_reply(&msg),
+        };
+        reply.ok_or(Error::Protocol(msg))
+    }
+
+    //Sends a request and reads the reply, keeping any events sent to a spectator meanwhile
+    fn request (&mut self, request: CommandData) -> Result<Reply> {
+        let msg = match self.mode {
+            Mode::Text => format_text_request(&request),
+            Mode::Json => json::format_request(&request),
+        };
+        self.connection.write_all(msg.as_bytes())?;
+
+        //The text protocol's moves and results look the same as the replies to players' requests,
+        //but can only be events when the request is one a spectator makes
+        let spectating = match request {
+            CommandData::Register(..) | CommandData::GetBoard(_) | CommandData::GetLegalMoves(..) |
+            CommandData::Message(..) | CommandData::Hello(..) => self.mode == Mode::Text,
+            _ => false,
+        };
+        loop {
+            match self.read_reply()? {
+                Reply::Event(event) => self.events.push_back(event),
+                Reply::Move(mov) if spectating => self.events.push_back(Event::Move(mov)),
+                Reply::GameOver(result) if spectating => self.events.push_back(Event::GameOver(result)),
+                reply => return Ok(reply),
+            }
+        }
+    }
+
+    //Works out what went wrong from a reply we weren't hoping for
+    fn unexpected (reply: Reply) -> Error {
+        match reply {
+            Reply::GameOver(result) => Error::GameOver(result),
+            Reply::Rejected(err) => Error::Rejected(err),
+            reply => Error::Protocol(format!("{:?}", reply)),
+        }
+    }
+
+    fn connect<A: ToSocketAddrs> (addr: A, mode: Mode) -> Result<API> {
+        let connection = TcpStream::connect(addr)?;
+
+        Ok(API {
             connection: connection,
+            mode: mode,
+            events: VecDeque::new(),
+        })
+    }
+
+    ///Connect to the server at the given socket address
+    pub fn new<A: ToSocketAddrs> (addr: A) -> Result<API> {
+        API::connect(addr, Mode::Text)
+    }
+
+    ///Connect to the server at the given socket address, speaking the
+    ///[JSON protocol](../server/index.html#json-protocol) rather than the text one. The methods
+    ///behave exactly the same either way.
+    pub fn new_json<A: ToSocketAddrs> (addr: A) -> Result<API> {
+        API::connect(addr, Mode::Json)
+    }
+
+    ///Register as a player with the server. If the game already has two players, you will be
+    ///a spectator instead.
+    pub fn register (&mut self, name: &str) -> Result<()> {
+        match self.request(CommandData::Register(name.to_string(), 0))? {
+            Reply::Registered => Ok(()),
+            reply => Err(API::unexpected(reply)),
         }
     }
 
-    ///Register as a player with the server
-    pub fn register (&mut self, name: &str) {
-        self.connection.write(
-            format!("Hello, my name is {}, you killed my father, prepare to die;",
-                    name)
-            .as_bytes()).unwrap();
-        API::read_message(&mut self.connection);
+    ///Move a piece from (mov.sx,mov.sy) to (mov.dx,mov.dy). Returns `Error::GameOver` if the
+    ///game has ended, including if this move won it.
+    ///
+    ///If the server won't make the move, returns `Error::Rejected` with one of:
+    ///
+    ///* `CommandErr::NoPiece` - there is no piece at (mov.sx,mov.sy)
+    ///* `CommandErr::WrongPiece` - the piece belongs to your opponent
+    ///* `CommandErr::IllegalMove` - the move breaks the rules, see `legal_moves`
+    ///* `CommandErr::NotYourTurn`
+    ///* `CommandErr::NotStarted`
+    ///* `CommandErr::NotRegistered`
+    pub fn move_piece (&mut self, mov: &Move) -> Result<()> {
+        match self.request(CommandData::Move(0, mov.clone()))? {
+            Reply::Ok => Ok(()),
+            reply => Err(API::unexpected(reply)),
+        }
     }
 
-    ///Move a piece from (mov.sx,mov.sy) to (mov.dx,mov.dy)
-    pub fn move_piece (&mut self, mov: &Move) {
-        self.connection.write(
-            format!("({},{}) -> ({},{});",
-                    mov.sx,mov.sy,mov.dx,mov.dy)
-            .as_bytes()).unwrap();
-        API::read_message(&mut self.connection);
+    ///Get the opponent's next move, or whatever else they did instead
+    pub fn get_move (&mut self) -> Result<Turn> {
+        match self.request(CommandData::GetMove(0))? {
+            Reply::Move(mov) => Ok(Turn::Move(mov)),
+            Reply::GameOver(result) => Ok(Turn::GameOver(result)),
+            Reply::DrawOffered => Ok(Turn::DrawOffered),
+            Reply::DrawDeclined => Ok(Turn::DrawDeclined),
+            reply => Err(API::unexpected(reply)),
+        }
+    }
+
+    ///Resign the game. Returns the result of the game.
+    pub fn resign (&mut self) -> Result<GameResult> {
+        match self.request(CommandData::Resign(0))? {
+            Reply::GameOver(result) => Ok(result),
+            reply => Err(API::unexpected(reply)),
+        }
+    }
+
+    ///Offer the opponent a draw. If they had already offered one, the game is drawn and the
+    ///result is returned.
+    pub fn offer_draw (&mut self) -> Result<Option<GameResult>> {
+        match self.request(CommandData::OfferDraw(0))? {
+            Reply::Ok => Ok(None),
+            Reply::GameOver(result) => Ok(Some(result)),
+            reply => Err(API::unexpected(reply)),
+        }
     }
 
-    //Get the opponent's next move
-    pub fn get_move (&mut self) -> Move {
-        self.connection.write(b"Gimmeh!;").unwrap();
-        let msg = API::read_message(&mut self.connection);
-        
-        let move_re = Regex::new(r"^\((\d),(\d)\) ?-> ?\((\d),(\d)\);")
-            .ok()
-            .expect("Failed to create regex");
-        
-        let caps = move_re.captures(&msg)
-            .expect("Failed to parse regex")
-            .iter()
-            .skip(1)
-            .map(|x:Option<&str>| { x.unwrap().parse::<u8>().unwrap() })
-            .collect::<Vec<_>>();
+    ///Accept the opponent's offer of a draw. Returns the result of the game.
+    pub fn accept_draw (&mut self) -> Result<GameResult> {
+        match self.request(CommandData::AcceptDraw(0))? {
+            Reply::GameOver(result) => Ok(result),
+            reply => Err(API::unexpected(reply)),
+        }
+    }
 
-        Move::new_vec(caps)
+    ///Decline the opponent's offer of a draw
+    pub fn decline_draw (&mut self) -> Result<()> {
+        match self.request(CommandData::DeclineDraw(0))? {
+            Reply::Ok => Ok(()),
+            reply => Err(API::unexpected(reply)),
+        }
     }
 
     ///Waits until an opponent is ready. Returns your colour and the opponent's name.
-    pub fn wait_on_start (&mut self) -> (Colour, String) {
-        self.connection.write(b"Bring it, yo;").unwrap();
-        let msg = API::read_message(&mut self.connection);
+    pub fn wait_on_start (&mut self) -> Result<(Colour, String)> {
+        match self.request(CommandData::Ready(0))? {
+            Reply::Start(colour, opponent) => Ok((colour, opponent)),
+            reply => Err(API::unexpected(reply)),
+        }
+    }
 
-        let reply_re = Regex::new(r"You are ([A-Za-z]+) and ([A-Za-z0-9]+) wants to batter you;")
-            .ok()
-            .expect("Bad regex");
+    ///Wait for the next event in the game. Only spectators, i.e. those who registered after
+    ///the game filled up, are sent events. Spectators can still get the board, legal moves and
+    ///handshake, and any events that arrive before the reply are kept for here.
+    pub fn next_event (&mut self) -> Result<Event> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        match self.read_reply()? {
+            Reply::Event(event) => Ok(event),
+            //The text protocol's moves and results look the same as the responses
+            Reply::Move(mov) => Ok(Event::Move(mov)),
+            Reply::GameOver(result) => Ok(Event::GameOver(result)),
+            reply => Err(API::unexpected(reply)),
+        }
+    }
+
+    ///Get the current state of the game
+    pub fn get_board (&mut self) -> Result<BoardState> {
+        match self.request(CommandData::GetBoard(0))? {
+            Reply::Board(state) => Ok(state),
+            reply => Err(API::unexpected(reply)),
+        }
+    }
 
-        let caps = reply_re.captures(&msg)
-            .expect("Regex didn't match");
+    ///Get the legal moves for the side to move
+    pub fn legal_moves (&mut self) -> Result<Vec<Move>> {
+        match self.request(CommandData::GetLegalMoves(0, None))? {
+            Reply::Moves(moves) => Ok(moves),
+            reply => Err(API::unexpected(reply)),
+        }
+    }
 
-        let colour = caps.at(1).unwrap().parse::<Colour>()
-            .ok()
-            .expect("Couldn't parse Colour");
+    ///Get the legal moves for the piece at (x,y)
+    pub fn legal_moves_from (&mut self, x: u8, y: u8) -> Result<Vec<Move>> {
+        match self.request(CommandData::GetLegalMoves(0, Some((x,y))))? {
+            Reply::Moves(moves) => Ok(moves),
+            reply => Err(API::unexpected(reply)),
+        }
+    }
 
-        (colour, caps.at(2).unwrap().to_string())
+    ///Tell the server which protocol versions we speak and find out what it can do. This is
+    ///optional, servers which don't know about it reject it with
+    ///`Error::Rejected(CommandErr::Unrecognised)`.
+    pub fn hello (&mut self) -> Result<Handshake> {
+        match self.request(CommandData::Hello(0, PROTOCOL_VERSION))? {
+            Reply::Hello(handshake) => Ok(handshake),
+            reply => Err(API::unexpected(reply)),
+        }
     }
 
-    ///Get the default port the server listens on
+    ///Get the port the server listens on unless it's told otherwise
     pub fn default_server_port() -> u16 {
-        return PORT;
+        return DEFAULT_PORT;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn rejection(msg: &str) -> Option<CommandErr> {
+        match parse_text_reply(msg) {
+            Some(Reply::Rejected(err)) => Some(err),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_rejections() {
+        assert_eq!(rejection("You wot m8?;"), Some(CommandErr::Unrecognised));
+        assert_eq!(rejection("Spit it out;"), Some(CommandErr::TooLong));
+        assert_eq!(rejection("That's not even text;"), Some(CommandErr::NotText));
+        assert_eq!(rejection("Wait your turn;"), Some(CommandErr::NotYourTurn));
+        assert_eq!(rejection("Something went wrong: oops;"), Some(CommandErr::Other("oops".to_string())));
+        assert_eq!(rejection("Move successful;"), None);
+    }
+
+    #[test]
+    fn parses_json_rejections() {
+        let reply = json::parse_reply("{\"type\":\"error\",\"error\":\"unrecognised\",\"message\":\"You wot m8?\"}");
+        match reply {
+            Some(Reply::Rejected(err)) => assert_eq!(err, CommandErr::Unrecognised),
+            other => panic!("Expected a rejection, got {:?}", other),
+        }
+    }
+
+    //Pretends to be a server, sending `replies` once a request has come in
+    fn serve_one_request(replies: String) -> API {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api = API::new(listener.local_addr().unwrap()).unwrap();
+        thread::spawn(move|| {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut c = [0;1];
+            while stream.read(&mut c).unwrap() == 1 && c[0] != b';' {}
+            stream.write_all(replies.as_bytes()).unwrap();
+        });
+        api
+    }
+
+    #[test]
+    fn keeps_events_sent_before_a_reply() {
+        let result = GameResult::Win(Colour::Black, WinReason::Connection);
+        let replies = format!("(1,0)->(1,2);Game over, {};The state of play: {}, White to move, move 2, game over, {};",
+                              result, Board::new().position(), result);
+        let mut api = serve_one_request(replies);
+
+        let state = api.get_board().unwrap();
+        assert_eq!(state.status, GameStatus::Over(result));
+        assert!(match api.next_event().unwrap() { Event::Move(mov) => mov == Move::new(1, 0, 1, 2), _ => false });
+        assert!(match api.next_event().unwrap() { Event::GameOver(r) => r == result, _ => false });
+    }
+
+    #[test]
+    fn old_servers_reject_hello() {
+        let mut api = serve_one_request("You wot m8?;".to_string());
+        assert!(match api.hello() { Err(Error::Rejected(CommandErr::Unrecognised)) => true, _ => false });
     }
 }
@@ -32,7 +32,7 @@ impl FromStr for Colour {
     }
 }
 
-#[derive(Clone,Copy)]
+#[derive(Clone,Copy,Debug)]
 pub struct Cell {
     pub has_piece: bool,
     pub colour: Colour,
@@ -47,6 +47,44 @@ impl Default for Cell {
     fn default() -> Cell {Cell {has_piece: false, colour: Colour::White} }
 }
 
+///The starting layouts the game can be played from. The rules are the same for each.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Variant {
+    ///Black along the top and bottom edges, White along the left and right
+    Standard,
+    ///Each edge alternates between Black and White
+    ScrambledEggs,
+}
+
+impl Variant {
+    ///Every variant there is
+    pub fn all() -> Vec<Variant> {
+        vec![Variant::Standard, Variant::ScrambledEggs]
+    }
+}
+
+impl Display for Variant {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),Error> {
+        write!(f, "{}", match *self {
+            Variant::Standard => "standard",
+            Variant::ScrambledEggs => "scrambled-eggs",
+        })
+    }
+}
+
+impl FromStr for Variant {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Variant, ()> {
+        match s {
+            "standard" => Ok(Variant::Standard),
+            "scrambled-eggs" => Ok(Variant::ScrambledEggs),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone,Debug)]
 pub struct Board {
     pub cells: [[Cell; 8]; 8],
 }
@@ -54,8 +92,12 @@ pub struct Board {
 pub enum MoveErr {
     NoPiece,
     WrongPiece,
+    ///The move is off the board or breaks the rules of movement
+    IllegalMove,
 }
 
+const DIRECTIONS: [(i8,i8); 8] = [(1,0),(-1,0),(0,1),(0,-1),(1,1),(-1,-1),(1,-1),(-1,1)];
+
 impl Board {
     pub fn new() -> Board {
         let mut board = Board {
@@ -72,11 +114,41 @@ impl Board {
         board
     }
 
+    ///The starting position for the given variant
+    pub fn new_variant(variant: Variant) -> Board {
+        match variant {
+            Variant::Standard => Board::new(),
+            Variant::ScrambledEggs => {
+                let mut board = Board {
+                    cells: [[Cell::default(); 8]; 8]
+                };
+                let last = (BOARD_SIZE-1) as usize;
+
+                for i in 1..last {
+                    let (odd, even) = if i % 2 == 1 {
+                        (Cell::black(), Cell::white())
+                    } else {
+                        (Cell::white(), Cell::black())
+                    };
+                    board.cells[i][0] = odd;
+                    board.cells[last][i] = odd;
+                    board.cells[i][last] = even;
+                    board.cells[0][i] = even;
+                }
+                board
+            },
+        }
+    }
+
     pub fn move_piece (&mut self, mov: &Move, player_colour: &Colour) -> Result<(),MoveErr> {
-        if !self.cells[mov.sx as usize] [mov.sy as usize].has_piece {
+        if mov.sx >= BOARD_SIZE || mov.sy >= BOARD_SIZE || mov.dx >= BOARD_SIZE || mov.dy >= BOARD_SIZE {
+            Err(MoveErr::IllegalMove)
+        } else if !self.cells[mov.sx as usize] [mov.sy as usize].has_piece {
             Err(MoveErr::NoPiece)
         } else if self.cells[mov.sx as usize] [mov.sy as usize].colour != *player_colour {
             Err(MoveErr::WrongPiece)
+        } else if !self.legal_moves_from(mov.sx, mov.sy).contains(mov) {
+            Err(MoveErr::IllegalMove)
         } else {
             self.cells[mov.dx as usize][mov.dy as usize] = self.cells[mov.sx as usize][mov.sy as usize];
             self.cells[mov.sx as usize][mov.sy as usize] = Cell::default();
@@ -87,4 +159,294 @@ impl Board {
     pub fn colour_at (&self, x:usize, y:usize) -> Colour {
         self.cells[x][y].colour
     }
+
+    fn on_board (x: i8, y: i8) -> bool {
+        x >= 0 && y >= 0 && x < BOARD_SIZE as i8 && y < BOARD_SIZE as i8
+    }
+
+    fn has_piece_at (&self, x: i8, y: i8) -> bool {
+        Board::on_board(x,y) && self.cells[x as usize][y as usize].has_piece
+    }
+
+    ///The legal moves for the piece at (x,y), or none if there is no piece there. A piece
+    ///moves in a straight line exactly as many cells as there are pieces on that line. It may
+    ///jump over its own pieces but not its opponent's, and may capture an opponent's piece
+    ///by landing on it.
+    pub fn legal_moves_from (&self, x: u8, y: u8) -> Vec<Move> {
+        if !self.has_piece_at(x as i8, y as i8) {
+            return Vec::new();
+        }
+        let colour = self.colour_at(x as usize, y as usize);
+
+        let mut moves = Vec::new();
+        for &(dx,dy) in DIRECTIONS.iter() {
+            //Count the pieces on the whole line, in both directions
+            let mut distance = 1;
+            for &(step_x,step_y) in [(dx,dy),(-dx,-dy)].iter() {
+                let (mut cx, mut cy) = (x as i8 + step_x, y as i8 + step_y);
+                while Board::on_board(cx,cy) {
+                    if self.has_piece_at(cx,cy) {
+                        distance += 1;
+                    }
+                    cx += step_x;
+                    cy += step_y;
+                }
+            }
+
+            let (tx, ty) = (x as i8 + dx*distance, y as i8 + dy*distance);
+            if !Board::on_board(tx,ty) {
+                continue;
+            }
+
+            let blocked = (1..distance).any(|i| {
+                let (cx, cy) = (x as i8 + dx*i, y as i8 + dy*i);
+                self.has_piece_at(cx,cy) && self.colour_at(cx as usize, cy as usize) != colour
+            });
+            let own_piece_at_target = self.has_piece_at(tx,ty)
+                && self.colour_at(tx as usize, ty as usize) == colour;
+
+            if !blocked && !own_piece_at_target {
+                moves.push(Move::new(x, y, tx as u8, ty as u8));
+            }
+        }
+        moves
+    }
+
+    ///All legal moves for the given colour
+    pub fn legal_moves (&self, colour: Colour) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for x in 0..BOARD_SIZE {
+            for y in 0..BOARD_SIZE {
+                let cell = self.cells[x as usize][y as usize];
+                if cell.has_piece && cell.colour == colour {
+                    moves.extend(self.legal_moves_from(x,y));
+                }
+            }
+        }
+        moves
+    }
+
+    ///Whether all of the pieces of the given colour form a single group. Pieces are
+    ///connected horizontally, vertically and diagonally.
+    pub fn is_connected (&self, colour: Colour) -> bool {
+        let size = BOARD_SIZE as usize;
+        let pieces = (0..size*size)
+            .map(|i| (i/size, i%size))
+            .filter(|&(x,y)| self.cells[x][y].has_piece && self.cells[x][y].colour == colour)
+            .collect::<Vec<_>>();
+
+        let first = match pieces.first() {
+            Some(&first) => first,
+            None => return true,
+        };
+
+        let mut seen = [[false; 8]; 8];
+        let mut to_visit = vec![first];
+        let mut n_seen = 0;
+        seen[first.0][first.1] = true;
+
+        while let Some((x,y)) = to_visit.pop() {
+            n_seen += 1;
+            for nx in x.saturating_sub(1)..(x+2).min(size) {
+                for ny in y.saturating_sub(1)..(y+2).min(size) {
+                    let cell = self.cells[nx][ny];
+                    if !seen[nx][ny] && cell.has_piece && cell.colour == colour {
+                        seen[nx][ny] = true;
+                        to_visit.push((nx,ny));
+                    }
+                }
+            }
+        }
+
+        n_seen == pieces.len()
+    }
+
+    ///The colour which has won by connecting their pieces, if any, after `mover` has moved.
+    ///If a move connects both sides, the mover wins.
+    pub fn winner (&self, mover: Colour) -> Option<Colour> {
+        if self.is_connected(mover) {
+            Some(mover)
+        } else if self.is_connected(mover.other()) {
+            Some(mover.other())
+        } else {
+            None
+        }
+    }
+
+    ///Textual notation for the position: one row per y coordinate, separated by `/`,
+    ///with `w` for white, `b` for black and `.` for an empty cell.
+    pub fn position (&self) -> String {
+        let mut rows = Vec::new();
+        for y in 0..BOARD_SIZE as usize {
+            let mut row = String::new();
+            for x in 0..BOARD_SIZE as usize {
+                let cell = self.cells[x][y];
+                row.push(match (cell.has_piece, cell.colour) {
+                    (false, _) => '.',
+                    (true, Colour::White) => 'w',
+                    (true, Colour::Black) => 'b',
+                });
+            }
+            rows.push(row);
+        }
+        rows.join("/")
+    }
+}
+
+impl Display for Board {
+    ///Draws the board in text, numbered along the top with x and down the side with y, using
+    ///the same letters as `Board::position`
+    fn fmt(&self, f: &mut Formatter) -> Result<(),Error> {
+        write!(f, " ")?;
+        for x in 0..BOARD_SIZE {
+            write!(f, " {}", x)?;
+        }
+        writeln!(f)?;
+
+        for (y, row) in self.position().split('/').enumerate() {
+            write!(f, "{}", y)?;
+            for c in row.chars() {
+                write!(f, " {}", c)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Board {
+    type Err = ();
+
+    ///Parses the notation produced by `Board::position`
+    fn from_str(s: &str) -> Result<Board, ()> {
+        let mut board = Board { cells: [[Cell::default(); 8]; 8] };
+        let rows = s.split('/').collect::<Vec<_>>();
+        if rows.len() != BOARD_SIZE as usize {
+            return Err(());
+        }
+
+        for (y,row) in rows.iter().enumerate() {
+            if row.len() != BOARD_SIZE as usize {
+                return Err(());
+            }
+            for (x,c) in row.chars().enumerate() {
+                board.cells[x][y] = match c {
+                    '.' => Cell::default(),
+                    'w' => Cell::white(),
+                    'b' => Cell::black(),
+                    _ => return Err(()),
+                };
+            }
+        }
+        Ok(board)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //An empty board with the given pieces on it
+    fn board_with(pieces: &[(u8,u8,Colour)]) -> Board {
+        let mut board = Board { cells: [[Cell::default(); 8]; 8] };
+        for &(x, y, colour) in pieces {
+            board.cells[x as usize][y as usize] = Cell { has_piece: true, colour: colour };
+        }
+        board
+    }
+
+    fn sorted(mut moves: Vec<Move>) -> Vec<(u8,u8)> {
+        let mut targets = moves.drain(..).map(|mov| (mov.dx, mov.dy)).collect::<Vec<_>>();
+        targets.sort();
+        targets
+    }
+
+    #[test]
+    fn moves_as_far_as_there_are_pieces_on_the_line() {
+        //Two on the row, two on the column, two on the diagonal and one on the other diagonal
+        let board = board_with(&[(3,3,Colour::Black), (0,3,Colour::White), (3,6,Colour::Black), (6,6,Colour::Black)]);
+        assert_eq!(sorted(board.legal_moves_from(3,3)),
+                   vec![(1,1), (1,3), (2,4), (3,1), (3,5), (4,2), (5,3), (5,5)]);
+    }
+
+    #[test]
+    fn jumps_own_pieces() {
+        let board = board_with(&[(2,3,Colour::Black), (3,3,Colour::Black), (4,3,Colour::Black)]);
+        assert!(board.legal_moves_from(2,3).contains(&Move::new(2,3,5,3)));
+    }
+
+    #[test]
+    fn blocked_by_enemy_pieces() {
+        let board = board_with(&[(3,3,Colour::Black), (4,3,Colour::White), (6,3,Colour::White)]);
+        assert!(!board.legal_moves_from(3,3).contains(&Move::new(3,3,6,3)));
+        assert!(board.legal_moves_from(3,3).contains(&Move::new(3,3,0,3)));
+    }
+
+    #[test]
+    fn can_land_on_enemy_pieces_but_not_own() {
+        let mut board = board_with(&[(3,3,Colour::Black), (5,3,Colour::White), (3,5,Colour::Black)]);
+        assert!(!board.legal_moves_from(3,3).contains(&Move::new(3,3,3,5)));
+
+        assert!(board.move_piece(&Move::new(3,3,5,3), &Colour::Black).is_ok());
+        assert!(!board.cells[3][3].has_piece);
+        assert_eq!(board.cells[5][3].colour, Colour::Black);
+        assert!(board.legal_moves(Colour::White).is_empty());
+    }
+
+    #[test]
+    fn stays_on_the_board() {
+        let board = board_with(&[(0,0,Colour::Black)]);
+        assert_eq!(sorted(board.legal_moves_from(0,0)), vec![(0,1), (1,0), (1,1)]);
+
+        //Three on the row, so it would go three to the left
+        let board = board_with(&[(1,3,Colour::Black), (2,3,Colour::Black), (3,3,Colour::Black)]);
+        let along_row = board.legal_moves_from(1,3).into_iter().filter(|mov| mov.dy == 3).collect::<Vec<_>>();
+        assert_eq!(sorted(along_row), vec![(4,3)]);
+
+        let mut board = Board::new();
+        assert!(match board.move_piece(&Move::new(1,0,1,8), &Colour::Black) { Err(MoveErr::IllegalMove) => true, _ => false });
+        assert!(match board.move_piece(&Move::new(3,3,3,4), &Colour::Black) { Err(MoveErr::NoPiece) => true, _ => false });
+        assert!(match board.move_piece(&Move::new(0,1,2,1), &Colour::Black) { Err(MoveErr::WrongPiece) => true, _ => false });
+    }
+
+    #[test]
+    fn connection() {
+        let board = Board::new();
+        assert!(!board.is_connected(Colour::Black));
+        assert!(!board.is_connected(Colour::White));
+        assert_eq!(board.winner(Colour::Black), None);
+
+        //Black's pieces touch, diagonally too, while White's are split
+        let board = board_with(&[(2,2,Colour::Black), (3,3,Colour::Black), (3,4,Colour::Black),
+                                 (0,0,Colour::White), (7,7,Colour::White)]);
+        assert!(board.is_connected(Colour::Black));
+        assert!(!board.is_connected(Colour::White));
+        assert_eq!(board.winner(Colour::White), Some(Colour::Black));
+
+        //If a move connects both sides, the mover wins
+        let board = board_with(&[(2,2,Colour::Black), (0,0,Colour::White)]);
+        assert_eq!(board.winner(Colour::White), Some(Colour::White));
+    }
+
+    #[test]
+    fn prints_as_text() {
+        assert_eq!(Board::new().to_string(),
+                   "  0 1 2 3 4 5 6 7\n\
+                    0 . b b b b b b .\n\
+                    1 w . . . . . . w\n\
+                    2 w . . . . . . w\n\
+                    3 w . . . . . . w\n\
+                    4 w . . . . . . w\n\
+                    5 w . . . . . . w\n\
+                    6 w . . . . . . w\n\
+                    7 . b b b b b b .\n");
+    }
+
+    #[test]
+    fn position_round_trips() {
+        for board in Variant::all().into_iter().map(Board::new_variant) {
+            assert_eq!(board.position().parse::<Board>().unwrap().position(), board.position());
+        }
+        assert!(".bbbbbb./w......w".parse::<Board>().is_err());
+    }
 }
@@ -0,0 +1,154 @@
+//! Chess clocks for timed games
+
+use std::time::{Duration,Instant};
+use std::fmt::{Formatter,Error,Display};
+use std::str::FromStr;
+use super::board::Colour;
+
+///How much time each side gets
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct TimeControl {
+    ///Each side's time for the whole game
+    pub initial: Duration,
+    ///Time added to a side's clock after each of their moves
+    pub increment: Duration,
+}
+
+impl Display for TimeControl {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),Error> {
+        write!(f, "{}+{}", self.initial.as_secs(), self.increment.as_secs())
+    }
+}
+
+impl FromStr for TimeControl {
+    type Err = ();
+
+    ///Parses `%seconds[+%increment]`, e.g. `300+5`
+    fn from_str(s: &str) -> Result<TimeControl, ()> {
+        let mut parts = s.splitn(2, '+');
+        let initial = parts.next().unwrap().parse::<u64>().map_err(|_| ())?;
+        let increment = match parts.next() {
+            Some(increment) => increment.parse::<u64>().map_err(|_| ())?,
+            None => 0,
+        };
+
+        if initial == 0 {
+            return Err(());
+        }
+        Ok(TimeControl {
+            initial: Duration::from_secs(initial),
+            increment: Duration::from_secs(increment),
+        })
+    }
+}
+
+///Both sides' clocks. Only the clock of the side to move runs.
+#[derive(Clone,Debug)]
+pub struct Clocks {
+    time_control: TimeControl,
+    white: Duration,
+    black: Duration,
+    //The side whose clock is running and when it last started
+    running: Option<(Colour,Instant)>,
+}
+
+impl Clocks {
+    pub fn new(time_control: TimeControl) -> Clocks {
+        Clocks {
+            time_control: time_control,
+            white: time_control.initial,
+            black: time_control.initial,
+            running: None,
+        }
+    }
+
+    pub fn time_control(&self) -> TimeControl {
+        self.time_control
+    }
+
+    fn stored_mut(&mut self, colour: Colour) -> &mut Duration {
+        match colour {
+            Colour::White => &mut self.white,
+            Colour::Black => &mut self.black,
+        }
+    }
+
+    ///The side whose clock is running, if either
+    pub fn running(&self) -> Option<Colour> {
+        self.running.map(|(colour, _)| colour)
+    }
+
+    ///The time the given side has left, counting down if their clock is running
+    pub fn remaining(&self, colour: Colour) -> Duration {
+        let stored = match colour {
+            Colour::White => self.white,
+            Colour::Black => self.black,
+        };
+
+        match self.running {
+            Some((running, since)) if running == colour =>
+                stored.checked_sub(since.elapsed()).unwrap_or(Duration::from_secs(0)),
+            _ => stored,
+        }
+    }
+
+    ///Starts the given side's clock, stopping the other's without an increment
+    pub fn start(&mut self, colour: Colour) {
+        self.stop();
+        self.running = Some((colour, Instant::now()));
+    }
+
+    ///Stops whichever clock is running
+    pub fn stop(&mut self) {
+        if let Some((colour, _)) = self.running {
+            let remaining = self.remaining(colour);
+            *self.stored_mut(colour) = remaining;
+            self.running = None;
+        }
+    }
+
+    ///Ends the running side's turn, giving them their increment, and starts the other side's
+    ///clock
+    pub fn switch(&mut self) {
+        if let Some((colour, _)) = self.running {
+            self.stop();
+            let increment = self.time_control.increment;
+            *self.stored_mut(colour) += increment;
+            self.start(colour.other());
+        }
+    }
+
+    ///The side which has run out of time, if any
+    pub fn flagged(&self) -> Option<Colour> {
+        match self.running {
+            Some((colour, _)) if self.remaining(colour) == Duration::from_secs(0) => Some(colour),
+            _ => None,
+        }
+    }
+}
+
+///Formats a time left as `m:ss`, the way a clock would show it
+pub fn format_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time_control(initial: u64, increment: u64) -> TimeControl {
+        TimeControl { initial: Duration::from_secs(initial), increment: Duration::from_secs(increment) }
+    }
+
+    #[test]
+    fn parses_time_controls() {
+        assert_eq!("300".parse::<TimeControl>(), Ok(time_control(300, 0)));
+        assert_eq!("300+5".parse::<TimeControl>(), Ok(time_control(300, 5)));
+        assert_eq!("1+0".parse::<TimeControl>(), Ok(time_control(1, 0)));
+        assert_eq!(time_control(60, 2).to_string().parse::<TimeControl>(), Ok(time_control(60, 2)));
+        for s in &["", "0", "0+5", "five", "300+", "+5", "-300", "300+-5", "300+5+1", "300 + 5"] {
+            assert!(s.parse::<TimeControl>().is_err(), "{}", s);
+        }
+    }
+}
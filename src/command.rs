@@ -1,5 +1,8 @@
-use std::sync::mpsc::Sender;
-use super::board::Colour;
+use std::sync::mpsc::{Sender,Receiver};
+use std::fmt::{Formatter,Error,Display};
+use std::str::FromStr;
+use std::time::Duration;
+use super::board::{Board,Colour};
 
 pub enum GetCommandErr {
     NoCommands,
@@ -8,9 +11,11 @@ pub enum GetCommandErr {
 
 pub trait CommandProducer {
     fn get_command(&self) -> Result<Command, GetCommandErr>;
+    ///Waits for a command, for at most `timeout` if given
+    fn wait_for_command(&self, timeout: Option<Duration>) -> Result<Command, GetCommandErr>;
 }
 
-#[derive(Clone,Debug)]
+#[derive(Clone,Debug,PartialEq)]
 pub struct Move {
     pub sx:u8,
     pub sy:u8,
@@ -29,15 +34,28 @@ impl Move {
     }
 }
 
+#[derive(Clone,Debug)]
 pub enum CommandData {
     Move (i32,Move),
     GetMove(i32),
     Ready(i32),
     Register(String,i32),
     Message(i32,String),
+    Resign(i32),
+    OfferDraw(i32),
+    AcceptDraw(i32),
+    DeclineDraw(i32),
+    Leave(i32),
+    GetBoard(i32),
+    ///Legal moves for the side to move, or for the piece on the given cell
+    GetLegalMoves(i32,Option<(u8,u8)>),
+    ///The highest protocol version the client speaks
+    Hello(i32,u32),
+    ///Stop serving, sent from within the server when it's told to quit
+    Shutdown,
 }
 
-#[derive(Debug)]
+#[derive(Clone,Debug,PartialEq)]
 pub enum CommandErr {
     AlreadyRegistered,
     NotRegistered,
@@ -45,17 +63,269 @@ pub enum CommandErr {
 
     NoPiece,
     WrongPiece,
+    IllegalMove,
+    NotYourTurn,
+
+    NotStarted,
+    NoDrawOffered,
+
+    ///The message wasn't a command the server knows
+    Unrecognised,
+    ///The message was longer than the server will read
+    TooLong,
+    ///The message wasn't valid UTF-8
+    NotText,
     
     Other(String),
 }
 
+///Every kind of error, for the protocols' tests to check they can all be read back
+#[cfg(test)]
+pub(crate) fn every_command_err() -> Vec<CommandErr> {
+    let errs = vec![
+        CommandErr::AlreadyRegistered, CommandErr::NotRegistered, CommandErr::GameFull,
+        CommandErr::NoPiece, CommandErr::WrongPiece, CommandErr::IllegalMove,
+        CommandErr::NotYourTurn, CommandErr::NotStarted, CommandErr::NoDrawOffered,
+        CommandErr::Unrecognised, CommandErr::TooLong, CommandErr::NotText,
+        CommandErr::Other("oops".to_string()),
+    ];
+    //Won't compile until a new variant is listed here, a reminder to add it above too
+    for err in &errs {
+        match *err {
+            CommandErr::AlreadyRegistered | CommandErr::NotRegistered | CommandErr::GameFull |
+            CommandErr::NoPiece | CommandErr::WrongPiece | CommandErr::IllegalMove |
+            CommandErr::NotYourTurn | CommandErr::NotStarted | CommandErr::NoDrawOffered |
+            CommandErr::Unrecognised | CommandErr::TooLong | CommandErr::NotText |
+            CommandErr::Other(_) => (),
+        }
+    }
+    errs
+}
+
+impl Display for CommandErr {
+    ///Writes the error as it's sent over the wire, without the `;`
+    fn fmt(&self, f: &mut Formatter) -> Result<(),Error> {
+        match *self {
+            CommandErr::AlreadyRegistered => write!(f, "You've already registered, you asshat"),
+            CommandErr::NotRegistered => write!(f, "Who even are you?"),
+            CommandErr::GameFull => write!(f, "The game is full, get tae"),
+            CommandErr::NoPiece => write!(f, "You can't move air, ya numpty"),
+            CommandErr::WrongPiece => write!(f, "Stick to your own pieces!"),
+            CommandErr::IllegalMove => write!(f, "That's no' how the pieces move"),
+            CommandErr::NotYourTurn => write!(f, "Wait your turn"),
+            CommandErr::NotStarted => write!(f, "Hold your horses, the game hasn't started"),
+            CommandErr::NoDrawOffered => write!(f, "Nobody offered you a draw"),
+            CommandErr::Unrecognised => write!(f, "You wot m8?"),
+            CommandErr::TooLong => write!(f, "Spit it out"),
+            CommandErr::NotText => write!(f, "That's not even text"),
+            CommandErr::Other(ref why) => write!(f, "Something went wrong: {}", why),
+        }
+    }
+}
+
+impl FromStr for CommandErr {
+    type Err = ();
+
+    ///Parses the format produced by `Display`
+    fn from_str(s: &str) -> Result<CommandErr, ()> {
+        if s.starts_with("Something went wrong: ") {
+            return Ok(CommandErr::Other(s["Something went wrong: ".len()..].to_string()));
+        }
+
+        match s {
+            "You've already registered, you asshat" => Ok(CommandErr::AlreadyRegistered),
+            "Who even are you?" => Ok(CommandErr::NotRegistered),
+            "The game is full, get tae" => Ok(CommandErr::GameFull),
+            "You can't move air, ya numpty" => Ok(CommandErr::NoPiece),
+            "Stick to your own pieces!" => Ok(CommandErr::WrongPiece),
+            "That's no' how the pieces move" => Ok(CommandErr::IllegalMove),
+            "Wait your turn" => Ok(CommandErr::NotYourTurn),
+            "Hold your horses, the game hasn't started" => Ok(CommandErr::NotStarted),
+            "Nobody offered you a draw" => Ok(CommandErr::NoDrawOffered),
+            "You wot m8?" => Ok(CommandErr::Unrecognised),
+            "Spit it out" => Ok(CommandErr::TooLong),
+            "That's not even text" => Ok(CommandErr::NotText),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum CommandOk {
     Ready(String, Colour),
     Move(Move),
+    Spectating(Receiver<Event>),
+    DrawOffered,
+    DrawDeclined,
+    GameOver(GameResult),
+    Board(BoardState),
+    Moves(Vec<Move>),
+    Hello(Handshake),
     None,
 }
 
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum GameStatus {
+    ///Not enough players are ready yet
+    Waiting,
+    InProgress,
+    Over(GameResult),
+}
+
+///A snapshot of the game
+#[derive(Clone,Debug)]
+pub struct BoardState {
+    pub board: Board,
+    ///The side to move
+    pub turn: Colour,
+    ///The number of the current move, starting at 1 and counting both sides' moves
+    pub move_number: u32,
+    pub status: GameStatus,
+}
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum WinReason {
+    ///The winner connected all of their pieces
+    Connection,
+    Resignation,
+    ///The loser went away
+    Disconnection,
+    ///The loser ran out of time
+    Timeout,
+}
+
+impl Display for WinReason {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),Error> {
+        write!(f, "{}", match *self {
+            WinReason::Connection => "connection",
+            WinReason::Resignation => "resignation",
+            WinReason::Disconnection => "disconnection",
+            WinReason::Timeout => "timeout",
+        })
+    }
+}
+
+impl FromStr for WinReason {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<WinReason, ()> {
+        match s {
+            "connection" => Ok(WinReason::Connection),
+            "resignation" => Ok(WinReason::Resignation),
+            "disconnection" => Ok(WinReason::Disconnection),
+            "timeout" => Ok(WinReason::Timeout),
+            _ => Err(()),
+        }
+    }
+}
+
+///The newest version of the protocol the server speaks
+pub const PROTOCOL_VERSION: u32 = 1;
+
+///Optional things the server can do, advertised in the hello exchange
+#[derive(Clone,Debug,PartialEq)]
+pub enum Capability {
+    ///The JSON protocol can be used
+    Json,
+    ///The game can be played with the named rules
+    Variant(String),
+    ///The game has a time control
+    Clocks,
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),Error> {
+        match *self {
+            Capability::Json => write!(f, "json"),
+            Capability::Variant(ref name) => write!(f, "variant:{}", name),
+            Capability::Clocks => write!(f, "clocks"),
+        }
+    }
+}
+
+impl FromStr for Capability {
+    type Err = ();
+
+    ///Parses the format produced by `Display`
+    fn from_str(s: &str) -> Result<Capability, ()> {
+        match s {
+            "json" => Ok(Capability::Json),
+            "clocks" => Ok(Capability::Clocks),
+            s if s.starts_with("variant:") && s.len() > "variant:".len() =>
+                Ok(Capability::Variant(s["variant:".len()..].to_string())),
+            _ => Err(()),
+        }
+    }
+}
+
+///What the server agreed to in the hello exchange
+#[derive(Clone,Debug,PartialEq)]
+pub struct Handshake {
+    ///The newest version spoken by both sides
+    pub version: u32,
+    pub capabilities: Vec<Capability>,
+}
+
+///How a game ended
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum GameResult {
+    Win(Colour,WinReason),
+    Draw,
+}
+
+impl Display for GameResult {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),Error> {
+        match *self {
+            GameResult::Win(colour,reason) =>
+                write!(f, "{:?} wins by {}", colour, reason),
+            GameResult::Draw => write!(f, "it's a draw"),
+        }
+    }
+}
+
+impl FromStr for GameResult {
+    type Err = ();
+
+    ///Parses the format produced by `Display`
+    fn from_str(s: &str) -> Result<GameResult, ()> {
+        if s == "it's a draw" {
+            return Ok(GameResult::Draw);
+        }
+
+        let words = s.split(' ').collect::<Vec<_>>();
+        if words.len() != 4 || words[1] != "wins" || words[2] != "by" {
+            return Err(());
+        }
+
+        Ok(GameResult::Win(words[0].parse::<Colour>()?, words[3].parse::<WinReason>()?))
+    }
+}
+
+///Things which happen in the game, pushed to spectators as they occur
+#[derive(Clone,Debug)]
+pub enum Event {
+    Position(Board),
+    Move(Move),
+    GameOver(GameResult),
+}
+
+//What the server said in reply to a request, or pushed to a spectator, as read by a client
+#[derive(Debug)]
+pub(crate) enum Reply {
+    Ok,
+    Registered,
+    Start(Colour,String),
+    Move(Move),
+    DrawOffered,
+    DrawDeclined,
+    GameOver(GameResult),
+    Board(BoardState),
+    Moves(Vec<Move>),
+    Hello(Handshake),
+    Event(Event),
+    Rejected(CommandErr),
+}
+
 
 pub type CommandResponse = Result<CommandOk,CommandErr>;
 
@@ -63,3 +333,51 @@ pub struct Command {
     pub data: CommandData,
     pub reply: Sender<CommandResponse>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_errs_round_trip() {
+        for err in every_command_err() {
+            assert_eq!(err.to_string().parse::<CommandErr>(), Ok(err));
+        }
+        assert_eq!("You wot m8?".parse::<CommandErr>(), Ok(CommandErr::Unrecognised));
+        assert!("Never heard of it".parse::<CommandErr>().is_err());
+    }
+
+    #[test]
+    fn game_results_round_trip() {
+        let reasons = [WinReason::Connection, WinReason::Resignation, WinReason::Disconnection,
+                       WinReason::Timeout];
+        for &colour in &[Colour::White, Colour::Black] {
+            for &reason in &reasons {
+                let result = GameResult::Win(colour, reason);
+                assert_eq!(result.to_string().parse::<GameResult>(), Ok(result));
+            }
+        }
+        assert_eq!(GameResult::Draw.to_string().parse::<GameResult>(), Ok(GameResult::Draw));
+        assert_eq!("Black wins by timeout".parse::<GameResult>(),
+                   Ok(GameResult::Win(Colour::Black, WinReason::Timeout)));
+        for s in &["", "draw", "Black wins", "Black wins by", "Black wins by magic",
+                   "Green wins by connection", "Black loses by connection",
+                   "Black wins by connection "] {
+            assert!(s.parse::<GameResult>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn capabilities_round_trip() {
+        let capabilities = vec![Capability::Json, Capability::Clocks,
+                                Capability::Variant("scrambled-eggs".to_string()),
+                                Capability::Variant("has:colon".to_string())];
+        for capability in capabilities {
+            assert_eq!(capability.to_string().parse::<Capability>(), Ok(capability));
+        }
+        assert_eq!("variant:standard".parse::<Capability>(), Ok(Capability::Variant("standard".to_string())));
+        for s in &["", "variant:", "variant", "JSON", "teleportation"] {
+            assert!(s.parse::<Capability>().is_err(), "{}", s);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+//! Pictures of positions as SVG or PNG images, for when there's no window to draw in
+
+extern crate png;
+
+use std::io::{self,Write};
+use std::fs::File;
+use std::path::Path;
+
+use super::board::{Board,Colour,BOARD_SIZE};
+use super::command::Move;
+
+//Sizes in pixels
+const CELL: u32 = 40;
+const MARGIN: u32 = 20;
+const SIZE: u32 = MARGIN + CELL * BOARD_SIZE as u32;
+const PIECE_RADIUS: f32 = 16.0;
+const ARROW_WIDTH: f32 = 6.0;
+const ARROW_HEAD: f32 = 16.0;
+
+type Rgb = (u8,u8,u8);
+
+//The same colours as the window, with the board chequered so cells are easier to count
+const BACKGROUND: Rgb = (75,45,25);
+const LIGHT_CELL: Rgb = (222,184,135);
+const DARK_CELL: Rgb = (181,136,99);
+const LABEL: Rgb = (255,255,255);
+const WHITE_PIECE: Rgb = (255,255,255);
+const BLACK_PIECE: Rgb = (0,0,0);
+const OUTLINE: Rgb = (128,128,128);
+const HIGHLIGHT: Rgb = (255,255,0);
+const HIGHLIGHT_OPACITY: f32 = 0.5;
+const ARROW: Rgb = (30,110,230);
+const ARROW_OPACITY: f32 = 0.8;
+
+///A position to draw, with some cells and moves picked out
+#[derive(Clone,Debug)]
+pub struct Diagram {
+    pub board: Board,
+    ///Cells to shade, e.g. where the last move went from and to
+    pub highlights: Vec<(u8,u8)>,
+    ///Moves to draw as arrows
+    pub arrows: Vec<Move>,
+}
+
+//The middle of the cell at (x,y)
+fn cell_centre(x: u8, y: u8) -> (f32,f32) {
+    ((MARGIN + x as u32 * CELL + CELL/2) as f32, (MARGIN + y as u32 * CELL + CELL/2) as f32)
+}
+
+//Where the shaft of an arrow ends and the three corners of its head, tip first
+fn arrow_shape(mov: &Move) -> ((f32,f32), [(f32,f32); 3]) {
+    let (sx, sy) = cell_centre(mov.sx, mov.sy);
+    let (dx, dy) = cell_centre(mov.dx, mov.dy);
+    let length = ((dx - sx).powi(2) + (dy - sy).powi(2)).sqrt();
+    let (ux, uy) = ((dx - sx) / length, (dy - sy) / length);
+    let (bx, by) = (dx - ux * ARROW_HEAD, dy - uy * ARROW_HEAD);
+    let half = ARROW_HEAD / 2.0;
+    ((bx, by), [(dx, dy), (bx - uy * half, by + ux * half), (bx + uy * half, by - ux * half)])
+}
+
+fn svg_colour((r,g,b): Rgb) -> String {
+    format!("rgb({},{},{})", r, g, b)
+}
+
+impl Diagram {
+    pub fn new(board: Board) -> Diagram {
+        Diagram {
+            board: board,
+            highlights: Vec::new(),
+            arrows: Vec::new(),
+        }
+    }
+
+    //The cells which have pieces on them, and their colours
+    fn pieces(&self) -> Vec<(u8,u8,Colour)> {
+        let mut pieces = Vec::new();
+        for x in 0..BOARD_SIZE {
+            for y in 0..BOARD_SIZE {
+                let cell = self.board.cells[x as usize][y as usize];
+                if cell.has_piece {
+                    pieces.push((x, y, cell.colour));
+                }
+            }
+        }
+        pieces
+    }
+
+    ///The diagram as an SVG document
+    pub fn svg(&self) -> String {
+        let mut out = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{0}\" viewBox=\"0 0 {0} {0}\">\n", SIZE);
+        out.push_str(&format!("<rect width=\"{0}\" height=\"{0}\" fill=\"{1}\"/>\n", SIZE, svg_colour(BACKGROUND)));
+
+        for i in 0..BOARD_SIZE {
+            let (centre, _) = cell_centre(i, 0);
+            let label = format!("font-family=\"sans-serif\" font-size=\"12\" fill=\"{}\" text-anchor=\"middle\" dominant-baseline=\"central\"",
+                                svg_colour(LABEL));
+            out.push_str(&format!("<text x=\"{}\" y=\"{}\" {}>{}</text>\n", centre, MARGIN/2, label, i));
+            out.push_str(&format!("<text x=\"{}\" y=\"{}\" {}>{}</text>\n", MARGIN/2, centre, label, i));
+        }
+
+        for x in 0..BOARD_SIZE {
+            for y in 0..BOARD_SIZE {
+                let colour = if (x + y) % 2 == 0 { LIGHT_CELL } else { DARK_CELL };
+                out.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"{2}\" height=\"{2}\" fill=\"{3}\"/>\n",
+                                      MARGIN + x as u32 * CELL, MARGIN + y as u32 * CELL, CELL, svg_colour(colour)));
+            }
+        }
+
+        for &(x,y) in &self.highlights {
+            out.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"{2}\" height=\"{2}\" fill=\"{3}\" fill-opacity=\"{4}\"/>\n",
+                                  MARGIN + x as u32 * CELL, MARGIN + y as u32 * CELL, CELL,
+                                  svg_colour(HIGHLIGHT), HIGHLIGHT_OPACITY));
+        }
+
+        for (x, y, colour) in self.pieces() {
+            let (cx, cy) = cell_centre(x, y);
+            let fill = match colour {
+                Colour::White => WHITE_PIECE,
+                Colour::Black => BLACK_PIECE,
+            };
+            out.push_str(&format!("<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\" stroke=\"{}\" stroke-width=\"1.5\"/>\n",
+                                  cx, cy, PIECE_RADIUS, svg_colour(fill), svg_colour(OUTLINE)));
+        }
+
+        for mov in &self.arrows {
+            let (sx, sy) = cell_centre(mov.sx, mov.sy);
+            let ((bx, by), head) = arrow_shape(mov);
+            out.push_str(&format!("<g fill=\"{0}\" stroke=\"{0}\" opacity=\"{1}\">\n", svg_colour(ARROW), ARROW_OPACITY));
+            out.push_str(&format!("<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke-width=\"{}\"/>\n",
+                                  sx, sy, bx, by, ARROW_WIDTH));
+            out.push_str(&format!("<polygon points=\"{},{} {},{} {},{}\" stroke=\"none\"/>\n",
+                                  head[0].0, head[0].1, head[1].0, head[1].1, head[2].0, head[2].1));
+            out.push_str("</g>\n");
+        }
+
+        out.push_str("</svg>\n");
+        out
+    }
+
+    ///The diagram as a PNG image
+    pub fn png(&self) -> Vec<u8> {
+        let mut canvas = Canvas::new(SIZE, SIZE, BACKGROUND);
+
+        for i in 0..BOARD_SIZE {
+            let (centre, _) = cell_centre(i, 0);
+            canvas.digit(i, centre, (MARGIN/2) as f32, LABEL);
+            canvas.digit(i, (MARGIN/2) as f32, centre, LABEL);
+        }
+
+        for x in 0..BOARD_SIZE {
+            for y in 0..BOARD_SIZE {
+                let colour = if (x + y) % 2 == 0 { LIGHT_CELL } else { DARK_CELL };
+                canvas.cell(x, y, colour, 1.0);
+            }
+        }
+
+        for &(x,y) in &self.highlights {
+            canvas.cell(x, y, HIGHLIGHT, HIGHLIGHT_OPACITY);
+        }
+
+        for (x, y, colour) in self.pieces() {
+            let (cx, cy) = cell_centre(x, y);
+            let fill = match colour {
+                Colour::White => WHITE_PIECE,
+                Colour::Black => BLACK_PIECE,
+            };
+            let bounds = (cx - CELL as f32 / 2.0, cy - CELL as f32 / 2.0, cx + CELL as f32 / 2.0, cy + CELL as f32 / 2.0);
+            canvas.fill(OUTLINE, 1.0, bounds, |px, py| (px - cx).powi(2) + (py - cy).powi(2) <= (PIECE_RADIUS + 0.75).powi(2));
+            canvas.fill(fill, 1.0, bounds, |px, py| (px - cx).powi(2) + (py - cy).powi(2) <= (PIECE_RADIUS - 0.75).powi(2));
+        }
+
+        for mov in &self.arrows {
+            let start = cell_centre(mov.sx, mov.sy);
+            let (end, head) = arrow_shape(mov);
+            let tip = head[0];
+            let bounds = (start.0.min(tip.0) - ARROW_HEAD, start.1.min(tip.1) - ARROW_HEAD,
+                          start.0.max(tip.0) + ARROW_HEAD, start.1.max(tip.1) + ARROW_HEAD);
+            //Drawn as one shape so the shaft and head don't darken where they overlap
+            canvas.fill(ARROW, ARROW_OPACITY, bounds, |px, py| {
+                distance_to_segment((px, py), start, end) <= ARROW_WIDTH / 2.0 || in_triangle((px, py), &head)
+            });
+        }
+
+        canvas.png()
+    }
+
+    ///Writes the diagram to `path`, as a PNG if it ends in `.png` and as SVG otherwise
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        let is_png = path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("png"));
+        if is_png {
+            file.write_all(&self.png())
+        } else {
+            file.write_all(self.svg().as_bytes())
+        }
+    }
+}
+
+fn distance_to_segment((px,py): (f32,f32), (ax,ay): (f32,f32), (bx,by): (f32,f32)) -> f32 {
+    let (dx, dy) = (bx - ax, by - ay);
+    let t = (((px - ax) * dx + (py - ay) * dy) / (dx * dx + dy * dy)).max(0.0).min(1.0);
+    ((px - ax - t * dx).powi(2) + (py - ay - t * dy).powi(2)).sqrt()
+}
+
+fn in_triangle((px,py): (f32,f32), corners: &[(f32,f32); 3]) -> bool {
+    let side = |(ax,ay): (f32,f32), (bx,by): (f32,f32)| (bx - ax) * (py - ay) - (by - ay) * (px - ax);
+    let (a, b, c) = (side(corners[0], corners[1]), side(corners[1], corners[2]), side(corners[2], corners[0]));
+    (a >= 0.0 && b >= 0.0 && c >= 0.0) || (a <= 0.0 && b <= 0.0 && c <= 0.0)
+}
+
+//3x5 pixel digits for the coordinates, a row to a byte with the leftmost pixel in the
+//highest of three bits
+const DIGITS: [[u8; 5]; 10] = [
+    [7,5,5,5,7], [2,6,2,2,7], [7,1,7,4,7], [7,1,7,1,7], [5,5,7,1,1],
+    [7,4,7,1,7], [7,4,7,5,7], [7,1,2,2,2], [7,5,7,5,7], [7,5,7,1,7],
+];
+const DIGIT_SCALE: u32 = 2;
+
+//An RGB image to draw the PNG in
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<Rgb>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32, background: Rgb) -> Canvas {
+        Canvas {
+            width: width,
+            height: height,
+            pixels: vec![background; (width * height) as usize],
+        }
+    }
+
+    fn blend(&mut self, x: u32, y: u32, (r,g,b): Rgb, opacity: f32) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let pixel = &mut self.pixels[(y * self.width + x) as usize];
+        let mix = |old: u8, new: u8| (old as f32 + (new as f32 - old as f32) * opacity).round() as u8;
+        *pixel = (mix(pixel.0, r), mix(pixel.1, g), mix(pixel.2, b));
+    }
+
+    fn cell(&mut self, x: u8, y: u8, colour: Rgb, opacity: f32) {
+        let (left, top) = (MARGIN + x as u32 * CELL, MARGIN + y as u32 * CELL);
+        for py in top..top + CELL {
+            for px in left..left + CELL {
+                self.blend(px, py, colour, opacity);
+            }
+        }
+    }
+
+    //Fills the shape within `(left, top, right, bottom)` containing the points for which
+    //`inside` is true, smoothing its edges by sampling each pixel at several points
+    fn fill<F: Fn(f32,f32) -> bool>(&mut self, colour: Rgb, opacity: f32, bounds: (f32,f32,f32,f32), inside: F) {
+        const SAMPLES: u32 = 4;
+        let (left, top, right, bottom) = bounds;
+        for y in top.max(0.0) as u32..(bottom.ceil() as u32).min(self.height) {
+            for x in left.max(0.0) as u32..(right.ceil() as u32).min(self.width) {
+                let mut hits = 0;
+                for sy in 0..SAMPLES {
+                    for sx in 0..SAMPLES {
+                        let px = x as f32 + (sx as f32 + 0.5) / SAMPLES as f32;
+                        let py = y as f32 + (sy as f32 + 0.5) / SAMPLES as f32;
+                        if inside(px, py) {
+                            hits += 1;
+                        }
+                    }
+                }
+                if hits > 0 {
+                    self.blend(x, y, colour, opacity * hits as f32 / (SAMPLES * SAMPLES) as f32);
+                }
+            }
+        }
+    }
+
+    //Draws a digit centred on (x,y)
+    fn digit(&mut self, digit: u8, x: f32, y: f32, colour: Rgb) {
+        let left = (x - (3 * DIGIT_SCALE) as f32 / 2.0).round() as u32;
+        let top = (y - (5 * DIGIT_SCALE) as f32 / 2.0).round() as u32;
+        for (row, bits) in DIGITS[digit as usize].iter().enumerate() {
+            for column in 0..3 {
+                if bits & (4 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..DIGIT_SCALE {
+                    for dx in 0..DIGIT_SCALE {
+                        self.blend(left + column * DIGIT_SCALE + dx, top + row as u32 * DIGIT_SCALE + dy, colour, 1.0);
+                    }
+                }
+            }
+        }
+    }
+
+    fn png(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 3);
+        for &(r,g,b) in &self.pixels {
+            data.extend_from_slice(&[r, g, b]);
+        }
+
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            //Writing to memory can't fail, and the data is the size the header says
+            let mut writer = encoder.write_header().expect("Couldn't write PNG header");
+            writer.write_image_data(&data).expect("Couldn't write PNG data");
+        }
+        png
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn svg_has_the_pieces_highlights_and_arrows() {
+        let mut diagram = Diagram::new(Board::new());
+        diagram.highlights = vec![(1,0), (1,2)];
+        diagram.arrows = vec![Move::new(1,0,1,2)];
+        let svg = diagram.svg();
+
+        let circles = svg.lines().filter(|line| line.starts_with("<circle")).collect::<Vec<_>>();
+        assert_eq!(circles.len(), 24);
+        let black = format!("fill=\"{}\"", svg_colour(BLACK_PIECE));
+        assert_eq!(circles.iter().filter(|circle| circle.contains(&black)).count(), 12);
+        //(1,0) is Black's
+        assert!(svg.contains(&format!("<circle cx=\"80\" cy=\"40\" r=\"16\" {}", black)));
+
+        let highlight = format!("fill=\"{}\" fill-opacity", svg_colour(HIGHLIGHT));
+        let highlights = svg.lines().filter(|line| line.contains(&highlight)).collect::<Vec<_>>();
+        assert_eq!(highlights, vec![
+            "<rect x=\"60\" y=\"20\" width=\"40\" height=\"40\" fill=\"rgb(255,255,0)\" fill-opacity=\"0.5\"/>",
+            "<rect x=\"60\" y=\"100\" width=\"40\" height=\"40\" fill=\"rgb(255,255,0)\" fill-opacity=\"0.5\"/>",
+        ]);
+
+        //The shaft runs from the middle of (1,0) to the head, which points at the middle of (1,2)
+        assert_eq!(svg.matches("<line").count(), 1);
+        assert!(svg.contains("<line x1=\"80\" y1=\"40\" x2=\"80\" y2=\"104\""));
+        assert!(svg.contains("<polygon points=\"80,120 72,104 88,104\""));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+
+    #[test]
+    fn png_decodes_to_the_drawing() {
+        let mut diagram = Diagram::new(Board::new());
+        diagram.highlights = vec![(0,0)];
+        let png = diagram.png();
+
+        let decoder = png::Decoder::new(&png[..]);
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height, info.color_type), (SIZE, SIZE, png::ColorType::Rgb));
+
+        let pixel = |(x, y): (f32, f32)| {
+            let i = ((y as u32 * SIZE + x as u32) * 3) as usize;
+            (pixels[i], pixels[i + 1], pixels[i + 2])
+        };
+        assert_eq!(pixel((1.0, 1.0)), BACKGROUND);
+        assert_eq!(pixel(cell_centre(1, 0)), BLACK_PIECE);
+        assert_eq!(pixel(cell_centre(0, 1)), WHITE_PIECE);
+        assert_eq!(pixel(cell_centre(3, 3)), LIGHT_CELL);
+        //Half way between the light cell and the highlight
+        assert_eq!(pixel(cell_centre(0, 0)), (239,220,68));
+    }
+}
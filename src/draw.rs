@@ -1,42 +1,294 @@
 extern crate sfml;
-use self::sfml::graphics::{RenderWindow,RectangleShape,RenderTarget,Color,CircleShape};
+use std::time::Duration;
+
+use self::sfml::graphics::{RenderWindow,RectangleShape,RenderTarget,Color,CircleShape,Font,Text};
 use self::sfml::system::vector2::Vector2f;
 
-use super::board::{Board,CELL_SIZE,Colour};
+use super::board::{Board,CELL_SIZE,BOARD_SIZE,SCREEN_SIZE,Colour};
+use super::command::Move;
+use super::clock;
+use super::theme::{Theme,Rgba};
+
+///Space left of and above the board for the coordinates, in the window's starting size
+pub const MARGIN: u32 = 20;
+///Height of the panel under the board which shows the players, in the window's starting size
+pub const PANEL_HEIGHT: u32 = 70;
+///The window's starting size
+pub const WINDOW_WIDTH: u32 = MARGIN + SCREEN_SIZE;
+pub const WINDOW_HEIGHT: u32 = MARGIN + SCREEN_SIZE + PANEL_HEIGHT;
 
 pub trait Drawable {
-    fn draw (&self, window: &mut RenderWindow) -> Result<(),String>;
+    fn draw (&self, window: &mut RenderWindow, theme: &Theme) -> Result<(),String>;
+}
+
+///Where things go in the window's current view, which is usually the whole window. Everything
+///is scaled from the window's starting size to fit, and centred across it.
+#[derive(Clone,Copy,Debug)]
+pub struct Layout {
+    ///The left edge of everything drawn
+    pub left: f32,
+    ///The top edge of everything drawn
+    pub top: f32,
+    ///How much bigger than the starting size everything is
+    pub scale: f32,
+}
+
+impl Layout {
+    pub fn new (window: &RenderWindow) -> Layout {
+        let view = window.get_view();
+        let (size, centre) = (view.get_size(), view.get_center());
+        let scale = (size.x / WINDOW_WIDTH as f32).min(size.y / WINDOW_HEIGHT as f32);
+        Layout {
+            left: centre.x - size.x / 2.0 + (size.x - WINDOW_WIDTH as f32 * scale) / 2.0,
+            top: centre.y - size.y / 2.0,
+            scale: scale,
+        }
+    }
+
+    ///The width and height of a cell
+    pub fn cell (&self) -> f32 {
+        CELL_SIZE as f32 * self.scale
+    }
+
+    ///The top left corner of the cell at (x,y)
+    pub fn cell_position (&self, x: f32, y: f32) -> Vector2f {
+        let margin = MARGIN as f32 * self.scale;
+        Vector2f::new(self.left + margin + x * self.cell(), self.top + margin + y * self.cell())
+    }
+}
+
+///The colour to draw something which is `colour` in a theme
+pub fn colour (colour: Rgba) -> Color {
+    let Rgba(r,g,b,a) = colour;
+    Color::new_rgba(r,g,b,a)
 }
 
+///The cell under a point in the window, if there is one
+pub fn cell_at (window: &RenderWindow, x: i32, y: i32) -> Option<(u8,u8)> {
+    let layout = Layout::new(window);
+    let corner = layout.cell_position(0.0, 0.0);
+    let (x, y) = ((x as f32 - corner.x) / layout.cell(), (y as f32 - corner.y) / layout.cell());
+    if x < 0.0 || y < 0.0 || x >= BOARD_SIZE as f32 || y >= BOARD_SIZE as f32 {
+        None
+    } else {
+        Some((x as u8, y as u8))
+    }
+}
+
+//The lines between the cells
+fn draw_grid (window: &mut RenderWindow, theme: &Theme) {
+    let layout = Layout::new(window);
+    let mut rect = RectangleShape::new().unwrap();
+    rect.set_fill_color(&Color::transparent());
+    rect.set_outline_color(&colour(theme.grid));
+    rect.set_outline_thickness(1.0);
+    rect.set_size2f(layout.cell(), layout.cell());
+
+    for x in 0..BOARD_SIZE {
+        for y in 0..BOARD_SIZE {
+            rect.set_position(&layout.cell_position(x as f32, y as f32));
+            window.draw(&rect);
+        }
+    }
+}
 
 impl Drawable for Board {
-    fn draw (&self, window: &mut RenderWindow) -> Result<(),String>{
-        let mut rect = RectangleShape::new().unwrap();
-        rect.set_fill_color(&Color::transparent());
-        rect.set_outline_color(&Color::black());
-        rect.set_outline_thickness(1.0);
-        rect.set_size2f(CELL_SIZE as f32,CELL_SIZE as f32);
+    fn draw (&self, window: &mut RenderWindow, theme: &Theme) -> Result<(),String>{
+        draw_grid(window, theme);
 
-        let mut piece = CircleShape::new().unwrap();
-        piece.set_radius((CELL_SIZE/2) as f32);
-        
         for (ri,row) in self.cells.iter().enumerate() {
             for (ci,cell) in row.iter().enumerate() {
-                let position = Vector2f::new((ri*(CELL_SIZE as usize)) as f32, (ci*(CELL_SIZE as usize)) as f32);
-                rect.set_position(&position);
-                window.draw(&rect);
-
                 if cell.has_piece {
-                    match cell.colour {
-                        Colour::White => piece.set_fill_color(&Color::white()),
-                        Colour::Black => piece.set_fill_color(&Color::black()),
-                    };
-                    piece.set_position(&position);
-                    window.draw(&piece);
+                    draw_piece(window, theme, cell.colour, ri as f32, ci as f32, 1.0);
                 }
             }
         }
-        
+
         Ok(())
     }
 }
+
+///Draws `board` part of the way through `mov`, with `progress` going from 0 before it to 1
+///once it's made. The piece slides along its line and any piece it captures fades out.
+pub fn draw_move (window: &mut RenderWindow, theme: &Theme, board: &Board, mov: &Move, progress: f32) {
+    draw_grid(window, theme);
+
+    for (ri,row) in board.cells.iter().enumerate() {
+        for (ci,cell) in row.iter().enumerate() {
+            let (x, y) = (ri as u8, ci as u8);
+            if !cell.has_piece || (x,y) == (mov.sx,mov.sy) {
+                continue;
+            }
+            let opacity = if (x,y) == (mov.dx,mov.dy) { 1.0 - progress } else { 1.0 };
+            draw_piece(window, theme, cell.colour, x as f32, y as f32, opacity);
+        }
+    }
+
+    let moving = board.cells[mov.sx as usize][mov.sy as usize];
+    if moving.has_piece {
+        let (sx, sy, dx, dy) = (mov.sx as f32, mov.sy as f32, mov.dx as f32, mov.dy as f32);
+        draw_piece(window, theme, moving.colour, sx + (dx - sx) * progress, sy + (dy - sy) * progress, 1.0);
+    }
+}
+
+///Draws one of `side`'s pieces on the cell at (x,y), which needn't be whole numbers for a
+///piece between cells. `opacity` goes from 0 (not there) to 1.
+pub fn draw_piece (window: &mut RenderWindow, theme: &Theme, side: Colour, x: f32, y: f32, opacity: f32) {
+    let layout = Layout::new(window);
+    let mut fill = colour(match side {
+        Colour::White => theme.white_piece,
+        Colour::Black => theme.black_piece,
+    });
+    fill.alpha = (fill.alpha as f32 * opacity).round() as u8;
+
+    let mut piece = CircleShape::new().unwrap();
+    piece.set_radius(layout.cell() / 2.0);
+    piece.set_fill_color(&fill);
+    piece.set_position(&layout.cell_position(x, y));
+    window.draw(&piece);
+}
+
+///Fills the cell at (x,y), e.g. to pick it out before the board is drawn over it
+pub fn highlight_cell (window: &mut RenderWindow, x: u8, y: u8, colour: &Color) {
+    let layout = Layout::new(window);
+    let mut rect = RectangleShape::new().unwrap();
+    rect.set_fill_color(colour);
+    rect.set_size2f(layout.cell(), layout.cell());
+    rect.set_position(&layout.cell_position(x as f32, y as f32));
+    window.draw(&rect);
+}
+
+///Picks out where a move went from and to
+pub fn highlight_move (window: &mut RenderWindow, theme: &Theme, mov: &Move) {
+    highlight_cell(window, mov.sx, mov.sy, &colour(theme.last_move));
+    highlight_cell(window, mov.dx, mov.dy, &colour(theme.last_move));
+}
+
+//Writing in the theme's colour
+fn text<'a> (font: &'a Font, theme: &Theme, string: &str, size: u32) -> Text<'a> {
+    let mut text = Text::new_init(string, font, size).unwrap();
+    text.set_color(&colour(theme.text));
+    text
+}
+
+///Numbers the columns along the top and the rows down the side, as cells are numbered in
+///the protocol
+pub fn draw_labels (window: &mut RenderWindow, theme: &Theme, font: &Font) {
+    let layout = Layout::new(window);
+    let size = (12.0 * layout.scale).round() as u32;
+    let margin = MARGIN as f32 * layout.scale;
+    for i in 0..BOARD_SIZE {
+        let mut label = text(font, theme, &i.to_string(), size);
+        let bounds = label.get_local_bounds();
+        let middle = layout.cell_position(i as f32 + 0.5, i as f32 + 0.5);
+
+        //Centred on the middle of the column or row, allowing for the space above letters
+        label.set_position2f(middle.x - bounds.left - bounds.width / 2.0,
+                             layout.top + (margin - bounds.height) / 2.0 - bounds.top);
+        window.draw(&label);
+        label.set_position2f(layout.left + (margin - bounds.width) / 2.0 - bounds.left,
+                             middle.y - bounds.top - bounds.height / 2.0);
+        window.draw(&label);
+    }
+}
+
+///One side's line in the panel under the board
+pub struct Side<'a> {
+    pub colour: Colour,
+    pub name: &'a str,
+    ///The time they have left, in a timed game
+    pub clock: Option<Duration>,
+    pub to_move: bool,
+}
+
+///Draws a line for each side under the board, picking out the side to move, and `status`
+///below them. Without a font only the pieces showing each side's colour are drawn.
+pub fn draw_panel (window: &mut RenderWindow, theme: &Theme, font: Option<&Font>, sides: &[Side], status: &str) {
+    let layout = Layout::new(window);
+    //Distances are given in the window's starting size
+    let scale = layout.scale;
+    let width = WINDOW_WIDTH as f32 * scale;
+    let text_size = (14.0 * scale).round() as u32;
+    let top = layout.top + (MARGIN + SCREEN_SIZE) as f32 * scale + 4.0 * scale;
+    let line = 20.0 * scale;
+
+    for (i, side) in sides.iter().enumerate() {
+        let y = top + i as f32 * line;
+
+        if side.to_move {
+            let mut rect = RectangleShape::new().unwrap();
+            rect.set_fill_color(&colour(theme.to_move));
+            rect.set_size2f(width, line);
+            rect.set_position2f(layout.left, y);
+            window.draw(&rect);
+        }
+
+        let mut piece = CircleShape::new().unwrap();
+        piece.set_radius(7.0 * scale);
+        piece.set_fill_color(&colour(match side.colour {
+            Colour::White => theme.white_piece,
+            Colour::Black => theme.black_piece,
+        }));
+        piece.set_outline_color(&colour(theme.outline));
+        piece.set_outline_thickness(1.0);
+        piece.set_position2f(layout.left + 6.0 * scale, y + 3.0 * scale);
+        window.draw(&piece);
+
+        if let Some(font) = font {
+            let mut name = text(font, theme, side.name, text_size);
+            name.set_position2f(layout.left + 26.0 * scale, y);
+            window.draw(&name);
+
+            if let Some(remaining) = side.clock {
+                let mut time = text(font, theme, &clock::format_time(remaining), text_size);
+                let time_width = time.get_local_bounds().width;
+                time.set_position2f(layout.left + width - time_width - 8.0 * scale, y);
+                window.draw(&time);
+            }
+        }
+    }
+
+    if let Some(font) = font {
+        let mut status = text(font, theme, status, (12.0 * scale).round() as u32);
+        status.set_position2f(layout.left + 6.0 * scale, top + sides.len() as f32 * line + 4.0 * scale);
+        window.draw(&status);
+    }
+}
+
+///Lists `moves` down the window's current view, two to a line as in a game record, picking
+///out the last of the first `current` and keeping it in sight
+pub fn draw_move_list (window: &mut RenderWindow, theme: &Theme, font: &Font, moves: &[String], current: usize) {
+    let view = window.get_view();
+    let (size, centre) = (view.get_size(), view.get_center());
+    let (left, top) = (centre.x - size.x / 2.0, centre.y - size.y / 2.0);
+    //Sized like the rest of the window, which fits its starting height
+    let scale = size.y / WINDOW_HEIGHT as f32;
+    let line = 16.0 * scale;
+    let text_size = (12.0 * scale).round() as u32;
+
+    let lines = (moves.len() + 1) / 2;
+    let visible = ((size.y / line) as usize).saturating_sub(1).max(1);
+    let current_line = current.saturating_sub(1) / 2;
+    let first = current_line.saturating_sub(visible / 2).min(lines.saturating_sub(visible));
+
+    for i in first..lines.min(first + visible) {
+        let y = top + (i - first) as f32 * line + 4.0 * scale;
+        let mut number = text(font, theme, &format!("{}.", i + 1), text_size);
+        number.set_position2f(left + 4.0 * scale, y);
+        window.draw(&number);
+
+        for (column, index) in (2*i..(2*i + 2).min(moves.len())).enumerate() {
+            let x = left + (30.0 + column as f32 * 55.0) * scale;
+            if index + 1 == current {
+                let mut rect = RectangleShape::new().unwrap();
+                rect.set_fill_color(&colour(theme.last_move));
+                rect.set_size2f(53.0 * scale, line);
+                rect.set_position2f(x - 2.0 * scale, y);
+                window.draw(&rect);
+            }
+            let mut mov = text(font, theme, &moves[index], text_size);
+            mov.set_position2f(x, y);
+            window.draw(&mov);
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! The SFML window which shows the game being played
+
+use std::sync::mpsc::{channel,Sender,Receiver};
+use std::path::Path;
+use std::time::{Duration,Instant};
+use std::thread;
+
+use sfml::window::{ContextSettings, VideoMode, event, DefaultStyle};
+use sfml::window::keyboard::Key;
+use sfml::window::mouse::MouseButton;
+use sfml::graphics::{RenderWindow, RenderTarget, Font, FloatRect, View as WindowView};
+
+use lines_of_action::board::{Board,Cell,Colour};
+use lines_of_action::command::{Command,CommandData,CommandResponse,CommandOk,Move};
+use lines_of_action::draw::{self,Drawable,Side,highlight_cell,highlight_move,WINDOW_WIDTH,WINDOW_HEIGHT};
+use lines_of_action::theme::Theme;
+use lines_of_action::replay::Replay;
+
+use super::{Engine,View,Playback,FRAME_MS};
+
+//Where to look for a font if none is given
+const DEFAULT_FONTS: &'static [&'static str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+//Someone playing by clicking on the window. They take a player's place in the engine and
+//send it commands just like a network client.
+struct Human {
+    id: i32,
+    reply: Sender<CommandResponse>,
+    replies: Receiver<CommandResponse>,
+    //The piece they've picked up
+    selected: Option<(u8,u8)>,
+    //Whether their opponent has offered a draw which they haven't answered
+    draw_offered: bool,
+}
+
+impl Human {
+    fn new(engine: &mut Engine, name: &str, colour: Colour) -> Human {
+        let (reply, replies) = channel();
+        let human = Human {
+            id: engine.server.new_id(),
+            reply: reply,
+            replies: replies,
+            selected: None,
+            draw_offered: false,
+        };
+
+        engine.reserve_colour(human.id, colour);
+        human.send(engine, CommandData::Register(name.to_string(), human.id));
+        human.send(engine, CommandData::Ready(human.id));
+        //Always waiting on the opponent when it isn't our move means we hear about draw
+        //offers and the end of the game straight away
+        human.send(engine, CommandData::GetMove(human.id));
+        human
+    }
+
+    fn send(&self, engine: &mut Engine, data: CommandData) {
+        engine.handle_command(Command{ data: data, reply: self.reply.clone() });
+    }
+
+    fn to_move(&self, engine: &Engine) -> bool {
+        engine.game_started && engine.result.is_none() &&
+            engine.players.get(&self.id).map(|p| p.colour) == Some(engine.turn)
+    }
+
+    //Deals with whatever the engine has said since last time
+    fn handle_replies(&mut self, engine: &mut Engine) {
+        while let Ok(response) = self.replies.try_recv() {
+            match response {
+                Ok(CommandOk::Move(_)) | Ok(CommandOk::DrawDeclined) =>
+                    self.send(engine, CommandData::GetMove(self.id)),
+                Ok(CommandOk::DrawOffered) => {
+                    info!("Your opponent offered a draw, press Y to accept or N to decline");
+                    self.draw_offered = true;
+                },
+                Ok(CommandOk::GameOver(result)) => {
+                    info!("Game over, {}", result);
+                    self.draw_offered = false;
+                },
+                Ok(ok) => debug!("Human got {:?}", ok),
+                Err(e) => warn!("The engine refused: {}", e),
+            }
+        }
+    }
+
+    //Accepts or declines the draw they've been offered
+    fn answer_draw(&mut self, engine: &mut Engine, accept: bool) {
+        if !self.draw_offered {
+            return;
+        }
+        self.draw_offered = false;
+
+        if accept {
+            self.send(engine, CommandData::AcceptDraw(self.id));
+        } else {
+            self.send(engine, CommandData::DeclineDraw(self.id));
+            self.send(engine, CommandData::GetMove(self.id));
+        }
+    }
+
+    fn click(&mut self, engine: &mut Engine, x: u8, y: u8) {
+        if !self.to_move(engine) {
+            self.selected = None;
+            return;
+        }
+
+        match self.selected.take() {
+            Some((sx,sy)) if engine.board.legal_moves_from(sx,sy).contains(&Move::new(sx,sy,x,y)) => {
+                self.send(engine, CommandData::Move(self.id, Move::new(sx,sy,x,y)));
+                self.send(engine, CommandData::GetMove(self.id));
+            },
+            _ => {
+                let cell = engine.board.cells[x as usize][y as usize];
+                if cell.has_piece && Some(cell.colour) == engine.players.get(&self.id).map(|p| p.colour) {
+                    self.selected = Some((x,y));
+                }
+            },
+        }
+    }
+
+    //Shows the piece they've picked up and where it can go
+    fn draw_selection(&self, engine: &Engine, window: &mut RenderWindow, theme: &Theme) {
+        if let Some((x,y)) = self.selected {
+            highlight_cell(window, x, y, &draw::colour(theme.selected));
+            for mov in engine.board.legal_moves_from(x,y) {
+                highlight_cell(window, mov.dx, mov.dy, &draw::colour(theme.destination));
+            }
+        }
+    }
+}
+
+//Loads the given font, or the first of the usual ones which can be found
+fn load_font(path: Option<&Path>) -> Option<Font> {
+    let font = match path {
+        Some(path) => path.to_str().and_then(Font::new_from_file),
+        None => DEFAULT_FONTS.iter().filter_map(|&path| Font::new_from_file(path)).next(),
+    };
+    if font.is_none() {
+        warn!("Couldn't load a font, so there won't be any writing in the window");
+    }
+    font
+}
+
+//A move being shown sliding from one cell to another
+struct Animation {
+    mov: Move,
+    started: Instant,
+}
+
+//Plays a move on the position shown. The engine has already checked it.
+fn play(board: &mut Board, mov: &Move) {
+    board.cells[mov.dx as usize][mov.dy as usize] = board.cells[mov.sx as usize][mov.sy as usize];
+    board.cells[mov.sx as usize][mov.sy as usize] = Cell::default();
+}
+
+fn open_window(width: u32, height: u32) -> RenderWindow {
+    match RenderWindow::new(VideoMode::new_init(width, height, 32),
+                            "Lines of Action",
+                            DefaultStyle,
+                            &ContextSettings::default()) {
+        Some(window) => window,
+        None => panic!("Cannot create a new Render Window.")
+    }
+}
+
+pub struct Gui {
+    window: RenderWindow,
+    human: Option<Human>,
+    //For the coordinates and the panel under the board
+    font: Option<Font>,
+    theme: Theme,
+    //The position on screen, which is behind the engine's while a move is being animated
+    shown: Board,
+    //How many of the engine's moves have been played on `shown`
+    shown_moves: usize,
+    animation: Option<Animation>,
+    //How long a move takes to animate, or zero not to
+    animation_time: Duration,
+}
+
+impl Gui {
+    ///Opens the window, with a person playing `human` if given. Text is written in the font
+    ///at `font`, or a common system font if not given. Moves take `animation_time` to slide
+    ///into place.
+    pub fn new(engine: &mut Engine, human: Option<&(String,Colour)>, font: Option<&Path>, theme: Theme,
+               animation_time: Duration) -> Gui {
+        Gui {
+            window: open_window(WINDOW_WIDTH, WINDOW_HEIGHT),
+            human: human.map(|&(ref name, colour)| Human::new(engine, name, colour)),
+            font: load_font(font),
+            theme: theme,
+            shown: engine.board.clone(),
+            shown_moves: engine.moves.len(),
+            animation: None,
+            animation_time: animation_time,
+        }
+    }
+
+    //Space lets the next move through when stepping, A, S and D switch between automatic,
+    //step and delayed playback, and Y and N accept and decline a draw
+    fn handle_key_press(&mut self, engine: &mut Engine, code: Key) {
+        debug!("Key pressed");
+        match code {
+            Key::Space => engine.step(),
+            Key::A => engine.set_playback(Playback::Automatic),
+            Key::S => engine.set_playback(Playback::Step),
+            Key::D => engine.set_playback(Playback::Delay),
+            Key::Y | Key::N => if let Some(ref mut human) = self.human {
+                human.answer_draw(engine, code == Key::Y);
+            },
+            _ => {},
+        }
+    }
+
+    fn handle_click(&mut self, engine: &mut Engine, x: i32, y: i32) {
+        if let (Some(ref mut human), Some((x,y))) = (self.human.as_mut(), draw::cell_at(&self.window, x, y)) {
+            human.click(engine, x, y);
+        }
+    }
+
+    //Finishes the move being animated once it's had its time, and starts on the next
+    fn animate(&mut self, engine: &Engine) {
+        if let Some(ref animation) = self.animation {
+            if animation.started.elapsed() < self.animation_time {
+                return;
+            }
+        }
+        if let Some(animation) = self.animation.take() {
+            play(&mut self.shown, &animation.mov);
+            self.shown_moves += 1;
+        }
+
+        //Rather than fall further and further behind a fast game, skip to its latest move
+        while self.shown_moves + 1 < engine.moves.len() {
+            play(&mut self.shown, &engine.moves[self.shown_moves]);
+            self.shown_moves += 1;
+        }
+
+        if let Some(mov) = engine.moves.get(self.shown_moves) {
+            if self.animation_time == Duration::from_secs(0) {
+                play(&mut self.shown, mov);
+                self.shown_moves += 1;
+            } else {
+                self.animation = Some(Animation { mov: mov.clone(), started: Instant::now() });
+            }
+        }
+    }
+
+    //The position, with the move being animated part way through
+    fn draw_board(&mut self, engine: &Engine) {
+        match self.animation {
+            Some(ref animation) => {
+                let progress = (animation.started.elapsed().as_secs_f32() / self.animation_time.as_secs_f32()).min(1.0);
+                //Easing in and out
+                let progress = progress * progress * (3.0 - 2.0 * progress);
+
+                highlight_move(&mut self.window, &self.theme, &animation.mov);
+                if let Some(ref human) = self.human {
+                    human.draw_selection(engine, &mut self.window, &self.theme);
+                }
+                draw::draw_move(&mut self.window, &self.theme, &self.shown, &animation.mov, progress);
+            },
+            None => {
+                if let Some(mov) = self.shown_moves.checked_sub(1).map(|i| &engine.moves[i]) {
+                    highlight_move(&mut self.window, &self.theme, mov);
+                }
+                if let Some(ref human) = self.human {
+                    human.draw_selection(engine, &mut self.window, &self.theme);
+                }
+                if let Err(e) = self.shown.draw(&mut self.window, &self.theme) {
+                    error!("Couldn't draw the board: {}", e);
+                }
+            },
+        }
+    }
+
+    //The names, clocks and whose turn it is, under the board
+    fn draw_panel(&mut self, engine: &Engine) {
+        let names = [engine.player_name(Colour::Black), engine.player_name(Colour::White)];
+        let sides = [Colour::Black, Colour::White].iter().zip(names.iter()).map(|(&colour, &name)| Side {
+            colour: colour,
+            //Sides aren't picked until both players are ready
+            name: if engine.game_started { name } else { "" },
+            clock: engine.clocks.as_ref().map(|clocks| clocks.remaining(colour)),
+            to_move: engine.game_started && engine.result.is_none() && engine.turn == colour,
+        }).collect::<Vec<_>>();
+
+        let draw_offered = self.human.as_ref().map_or(false, |human| human.draw_offered);
+        let status = match engine.result {
+            Some(result) => format!("Game over, {}", result),
+            None if draw_offered => "Your opponent offers a draw: Y to accept, N to decline".to_string(),
+            None if engine.game_started => format!("{:?} to move", engine.turn),
+            None => "Waiting for players".to_string(),
+        };
+
+        draw::draw_panel(&mut self.window, &self.theme, self.font.as_ref(), &sides, &status);
+    }
+}
+
+impl View for Gui {
+    fn update(&mut self, engine: &mut Engine) -> bool {
+        if let Some(ref mut human) = self.human {
+            human.handle_replies(engine);
+        }
+
+        // Handle events
+        for event in self.window.events() {
+            match event {
+                event::Closed => self.window.close(),
+                //Draw at the new size rather than stretching what was there
+                event::Resized{width, height} => {
+                    let view = WindowView::new_from_rect(&FloatRect::new(0.0, 0.0, width as f32, height as f32)).unwrap();
+                    self.window.set_view(&view);
+                },
+                event::KeyPressed{code: c,..} => self.handle_key_press(engine, c),
+                event::MouseButtonPressed{button: MouseButton::MouseLeft, x, y} =>
+                    self.handle_click(engine, x, y),
+                _             => {/* do nothing */}
+            }
+        }
+        if !self.window.is_open() {
+            return false;
+        }
+
+        // Clear the window
+        self.window.clear(&draw::colour(self.theme.background));
+
+        self.animate(engine);
+        self.draw_board(engine);
+        if let Some(ref font) = self.font {
+            draw::draw_labels(&mut self.window, &self.theme, font);
+        }
+        self.draw_panel(engine);
+
+        // Display things on screen
+        self.window.display();
+        true
+    }
+}
+
+//How wide the move list beside the board is when replaying, in the window's starting size
+const MOVE_LIST_WIDTH: u32 = 150;
+
+//The parts of a window `width` by `height` for the board and the move list beside it, which
+//each keep the same share of the window
+fn replay_views(width: u32, height: u32) -> (WindowView, WindowView) {
+    let share = WINDOW_WIDTH as f32 / (WINDOW_WIDTH + MOVE_LIST_WIDTH) as f32;
+    let board_width = width as f32 * share;
+
+    let mut board = WindowView::new_from_rect(&FloatRect::new(0.0, 0.0, board_width, height as f32)).unwrap();
+    board.set_viewport(&FloatRect::new(0.0, 0.0, share, 1.0));
+    let mut list = WindowView::new_from_rect(&FloatRect::new(0.0, 0.0, width as f32 - board_width, height as f32)).unwrap();
+    list.set_viewport(&FloatRect::new(share, 0.0, 1.0 - share, 1.0));
+    (board, list)
+}
+
+///Shows a recorded game until the window is closed. The left and right arrow keys step
+///through the moves, and up and down (or Home and End) jump to the start and end.
+pub fn replay(mut replay: Replay, font: Option<&Path>, theme: &Theme) {
+    let mut window = open_window(WINDOW_WIDTH + MOVE_LIST_WIDTH, WINDOW_HEIGHT);
+    let font = load_font(font);
+    let (mut board_view, mut list_view) = replay_views(WINDOW_WIDTH + MOVE_LIST_WIDTH, WINDOW_HEIGHT);
+
+    while window.is_open() {
+        for event in window.events() {
+            match event {
+                event::Closed => window.close(),
+                event::Resized{width, height} => {
+                    let (board, list) = replay_views(width, height);
+                    board_view = board;
+                    list_view = list;
+                },
+                event::KeyPressed{code, ..} => match code {
+                    Key::Right => replay.forward(),
+                    Key::Left => replay.back(),
+                    Key::Up | Key::Home => replay.to_start(),
+                    Key::Down | Key::End => replay.to_end(),
+                    Key::Escape | Key::Q => window.close(),
+                    _ => {},
+                },
+                _ => {},
+            }
+        }
+        if !window.is_open() {
+            break;
+        }
+
+        window.clear(&draw::colour(theme.background));
+        window.set_view(&board_view);
+        if let Some(mov) = replay.last_move() {
+            highlight_move(&mut window, theme, mov);
+        }
+        if let Err(e) = replay.board().draw(&mut window, theme) {
+            error!("Couldn't draw the board: {}", e);
+        }
+
+        let at_end = replay.current() == replay.move_count();
+        let names = [replay.record.black.as_str(), replay.record.white.as_str()];
+        let sides = [Colour::Black, Colour::White].iter().zip(names.iter()).map(|(&colour, &name)| Side {
+            colour: colour,
+            name: name,
+            clock: None,
+            to_move: !(at_end && replay.record.result.is_some()) && replay.turn() == colour,
+        }).collect::<Vec<_>>();
+        draw::draw_panel(&mut window, theme, font.as_ref(), &sides, &replay.status());
+
+        if let Some(ref font) = font {
+            draw::draw_labels(&mut window, theme, font);
+            window.set_view(&list_view);
+            draw::draw_move_list(&mut window, theme, font, replay.notation(), replay.current());
+        }
+
+        window.display();
+        thread::sleep(Duration::from_millis(FRAME_MS));
+    }
+}
@@ -0,0 +1,480 @@
+//! The JSON flavour of the protocol, documented [here](../server/index.html#json-protocol).
+//! Used by both the server and `api::API`.
+
+extern crate serde_json;
+
+use self::serde_json::{Value,Map};
+use super::command::{Move,CommandData,CommandResponse,CommandOk,CommandErr,Event,GameResult,
+                     WinReason,GameStatus,BoardState,Handshake,Capability,Reply};
+use super::board::{Board,Colour};
+
+//The name of an error on the wire
+fn error_code(err: &CommandErr) -> &'static str {
+    match *err {
+        CommandErr::AlreadyRegistered => "already_registered",
+        CommandErr::NotRegistered => "not_registered",
+        CommandErr::GameFull => "game_full",
+        CommandErr::NoPiece => "no_piece",
+        CommandErr::WrongPiece => "wrong_piece",
+        CommandErr::IllegalMove => "illegal_move",
+        CommandErr::NotYourTurn => "not_your_turn",
+        CommandErr::NotStarted => "not_started",
+        CommandErr::NoDrawOffered => "no_draw_offered",
+        CommandErr::Unrecognised => "unrecognised",
+        CommandErr::TooLong => "too_long",
+        CommandErr::NotText => "not_text",
+        CommandErr::Other(_) => "other",
+    }
+}
+
+//Reads back the codes written by `error_code`, except `Other`'s which needs its message
+fn parse_error_code(code: &str) -> Option<CommandErr> {
+    match code {
+        "already_registered" => Some(CommandErr::AlreadyRegistered),
+        "not_registered" => Some(CommandErr::NotRegistered),
+        "game_full" => Some(CommandErr::GameFull),
+        "no_piece" => Some(CommandErr::NoPiece),
+        "wrong_piece" => Some(CommandErr::WrongPiece),
+        "illegal_move" => Some(CommandErr::IllegalMove),
+        "not_your_turn" => Some(CommandErr::NotYourTurn),
+        "not_started" => Some(CommandErr::NotStarted),
+        "no_draw_offered" => Some(CommandErr::NoDrawOffered),
+        "unrecognised" => Some(CommandErr::Unrecognised),
+        "too_long" => Some(CommandErr::TooLong),
+        "not_text" => Some(CommandErr::NotText),
+        _ => None,
+    }
+}
+
+fn object(kind: &str) -> Map<String,Value> {
+    let mut object = Map::new();
+    object.insert("type".to_string(), Value::from(kind));
+    object
+}
+
+//Events have an `event` field instead, so they can't be mistaken for responses
+fn event_object(kind: &str) -> Map<String,Value> {
+    let mut object = Map::new();
+    object.insert("event".to_string(), Value::from(kind));
+    object
+}
+
+fn to_line(object: Map<String,Value>) -> String {
+    format!("{}\n", Value::Object(object))
+}
+
+fn cell_to_json(x: u8, y: u8) -> Value {
+    Value::Array(vec![Value::from(x), Value::from(y)])
+}
+
+fn cell_from_json(value: Option<&Value>) -> Option<(u8,u8)> {
+    let cell = value.and_then(Value::as_array)?;
+    if cell.len() != 2 {
+        return None;
+    }
+
+    match (cell[0].as_u64(), cell[1].as_u64()) {
+        (Some(x), Some(y)) if x < 256 && y < 256 => Some((x as u8, y as u8)),
+        _ => None,
+    }
+}
+
+fn add_move(object: &mut Map<String,Value>, mov: &Move) {
+    object.insert("from".to_string(), cell_to_json(mov.sx, mov.sy));
+    object.insert("to".to_string(), cell_to_json(mov.dx, mov.dy));
+}
+
+fn move_from_json(object: &Value) -> Option<Move> {
+    let (sx, sy) = cell_from_json(object.get("from"))?;
+    let (dx, dy) = cell_from_json(object.get("to"))?;
+    Some(Move::new(sx, sy, dx, dy))
+}
+
+fn add_result(object: &mut Map<String,Value>, result: GameResult) {
+    match result {
+        GameResult::Win(colour, reason) => {
+            object.insert("winner".to_string(), Value::from(format!("{:?}", colour)));
+            object.insert("reason".to_string(), Value::from(format!("{}", reason)));
+        },
+        GameResult::Draw => { object.insert("winner".to_string(), Value::Null); },
+    }
+}
+
+fn result_from_json(object: &Value) -> Option<GameResult> {
+    match object.get("winner") {
+        Some(&Value::Null) => Some(GameResult::Draw),
+        Some(winner) => {
+            let colour = winner.as_str()?.parse::<Colour>().ok()?;
+            let reason = object.get("reason")?.as_str()?.parse::<WinReason>().ok()?;
+            Some(GameResult::Win(colour, reason))
+        },
+        None => None,
+    }
+}
+
+fn str_field<'a>(object: &'a Value, field: &str) -> Option<&'a str> {
+    object.get(field).and_then(Value::as_str)
+}
+
+///Parses a request from a client, or returns `None` if it isn't one
+pub fn parse_request(msg: &str, id: i32) -> Option<CommandData> {
+    let request = serde_json::from_str::<Value>(msg).ok()?;
+
+    match str_field(&request, "type")? {
+        "register" => {
+            let name = str_field(&request, "name")?;
+            //Names are restricted so they can be sent to text clients
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return None;
+            }
+            Some(CommandData::Register(name.to_string(), id))
+        },
+        "ready" => Some(CommandData::Ready(id)),
+        "move" => Some(CommandData::Move(id, move_from_json(&request)?)),
+        "get_move" => Some(CommandData::GetMove(id)),
+        "message" => Some(CommandData::Message(id, str_field(&request, "text")?.to_string())),
+        "resign" => Some(CommandData::Resign(id)),
+        "offer_draw" => Some(CommandData::OfferDraw(id)),
+        "accept_draw" => Some(CommandData::AcceptDraw(id)),
+        "decline_draw" => Some(CommandData::DeclineDraw(id)),
+        "get_board" => Some(CommandData::GetBoard(id)),
+        "legal_moves" => match request.get("from") {
+            Some(from) => Some(CommandData::GetLegalMoves(id, Some(cell_from_json(Some(from))?))),
+            None => Some(CommandData::GetLegalMoves(id, None)),
+        },
+        "hello" => match request.get("version").and_then(Value::as_u64) {
+            Some(version) if version >= 1 && version <= u32::max_value() as u64 =>
+                Some(CommandData::Hello(id, version as u32)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+///Formats a request to send to the server
+pub fn format_request(request: &CommandData) -> String {
+    let object = match *request {
+        CommandData::Register(ref name, _) => {
+            let mut object = self::object("register");
+            object.insert("name".to_string(), Value::from(name.clone()));
+            object
+        },
+        CommandData::Ready(_) => object("ready"),
+        CommandData::Move(_, ref mov) => {
+            let mut object = self::object("move");
+            add_move(&mut object, mov);
+            object
+        },
+        CommandData::GetMove(_) => object("get_move"),
+        CommandData::Message(_, ref text) => {
+            let mut object = self::object("message");
+            object.insert("text".to_string(), Value::from(text.clone()));
+            object
+        },
+        CommandData::Resign(_) => object("resign"),
+        CommandData::OfferDraw(_) => object("offer_draw"),
+        CommandData::AcceptDraw(_) => object("accept_draw"),
+        CommandData::DeclineDraw(_) => object("decline_draw"),
+        CommandData::GetBoard(_) => object("get_board"),
+        CommandData::GetLegalMoves(_, cell) => {
+            let mut object = self::object("legal_moves");
+            if let Some((x, y)) = cell {
+                object.insert("from".to_string(), cell_to_json(x, y));
+            }
+            object
+        },
+        CommandData::Hello(_, version) => {
+            let mut object = self::object("hello");
+            object.insert("version".to_string(), Value::from(version));
+            object
+        },
+        CommandData::Leave(_) => panic!("Leaving is done by closing the connection"),
+        CommandData::Shutdown => panic!("Only the server can shut itself down"),
+    };
+    to_line(object)
+}
+
+pub fn format_error(code: &str, message: &str) -> String {
+    let mut object = object("error");
+    object.insert("error".to_string(), Value::from(code));
+    object.insert("message".to_string(), Value::from(message));
+    to_line(object)
+}
+
+pub fn format_command_err(err: &CommandErr) -> String {
+    format_error(error_code(err), &format!("{}", err))
+}
+
+///Formats the engine's response to a request
+pub fn format_response(request: &CommandData, response: &CommandResponse) -> String {
+    let ok = match *response {
+        Ok(ref ok) => ok,
+        Err(ref err) => return format_command_err(err),
+    };
+
+    let object = match (request, ok) {
+        (&CommandData::Register(ref name, _), _) => {
+            let mut object = object("registered");
+            object.insert("name".to_string(), Value::from(name.clone()));
+            let spectator = if let CommandOk::Spectating(_) = *ok { true } else { false };
+            object.insert("spectator".to_string(), Value::from(spectator));
+            object
+        },
+        (_, &CommandOk::Ready(ref opponent, colour)) => {
+            let mut object = object("start");
+            object.insert("colour".to_string(), Value::from(format!("{:?}", colour)));
+            object.insert("opponent".to_string(), Value::from(opponent.clone()));
+            object
+        },
+        (_, &CommandOk::Move(ref mov)) => {
+            let mut object = object("move");
+            add_move(&mut object, mov);
+            object
+        },
+        (_, &CommandOk::DrawOffered) => object("draw_offered"),
+        (_, &CommandOk::DrawDeclined) => object("draw_declined"),
+        (_, &CommandOk::GameOver(result)) => {
+            let mut object = object("game_over");
+            add_result(&mut object, result);
+            object
+        },
+        (_, &CommandOk::Board(ref state)) => {
+            let mut object = object("board");
+            object.insert("position".to_string(), Value::from(state.board.position()));
+            object.insert("turn".to_string(), Value::from(format!("{:?}", state.turn)));
+            object.insert("move_number".to_string(), Value::from(state.move_number));
+            match state.status {
+                GameStatus::Waiting => { object.insert("status".to_string(), Value::from("waiting")); },
+                GameStatus::InProgress => { object.insert("status".to_string(), Value::from("in_progress")); },
+                GameStatus::Over(result) => {
+                    object.insert("status".to_string(), Value::from("game_over"));
+                    add_result(&mut object, result);
+                },
+            }
+            object
+        },
+        (_, &CommandOk::Moves(ref moves)) => {
+            let mut object = object("moves");
+            let moves = moves.iter()
+                .map(|mov| {
+                    let mut object = Map::new();
+                    add_move(&mut object, mov);
+                    Value::Object(object)
+                })
+                .collect::<Vec<_>>();
+            object.insert("moves".to_string(), Value::Array(moves));
+            object
+        },
+        (_, &CommandOk::Hello(ref handshake)) => {
+            let mut object = object("hello");
+            object.insert("version".to_string(), Value::from(handshake.version));
+            let capabilities = handshake.capabilities.iter()
+                .map(|c| Value::from(c.to_string()))
+                .collect::<Vec<_>>();
+            object.insert("capabilities".to_string(), Value::Array(capabilities));
+            object
+        },
+        (_, &CommandOk::None) => object("ok"),
+        (_, &CommandOk::Spectating(_)) => {
+            error!("Unexpected response from engine: {:?}", ok);
+            return format_command_err(&CommandErr::Other("unexpected response".to_string()));
+        },
+    };
+    to_line(object)
+}
+
+pub fn format_event(event: &Event) -> String {
+    let object = match *event {
+        Event::Position(ref board) => {
+            let mut object = event_object("position");
+            object.insert("position".to_string(), Value::from(board.position()));
+            object
+        },
+        Event::Move(ref mov) => {
+            let mut object = event_object("move");
+            add_move(&mut object, mov);
+            object
+        },
+        Event::GameOver(result) => {
+            let mut object = event_object("game_over");
+            add_result(&mut object, result);
+            object
+        },
+    };
+    to_line(object)
+}
+
+///Parses a reply or event from the server
+pub fn parse_reply(msg: &str) -> Option<Reply> {
+    let reply = serde_json::from_str::<Value>(msg).ok()?;
+
+    if let Some(kind) = str_field(&reply, "event") {
+        return match kind {
+            "position" => Some(Event::Position(str_field(&reply, "position")?.parse::<Board>().ok()?)),
+            "move" => Some(Event::Move(move_from_json(&reply)?)),
+            "game_over" => Some(Event::GameOver(result_from_json(&reply)?)),
+            _ => None,
+        }.map(Reply::Event);
+    }
+
+    match str_field(&reply, "type")? {
+        "ok" => Some(Reply::Ok),
+        "registered" => Some(Reply::Registered),
+        "start" => Some(Reply::Start(str_field(&reply, "colour")?.parse::<Colour>().ok()?,
+                                     str_field(&reply, "opponent")?.to_string())),
+        "move" => Some(Reply::Move(move_from_json(&reply)?)),
+        "draw_offered" => Some(Reply::DrawOffered),
+        "draw_declined" => Some(Reply::DrawDeclined),
+        "game_over" => Some(Reply::GameOver(result_from_json(&reply)?)),
+        "board" => {
+            let status = match str_field(&reply, "status")? {
+                "waiting" => GameStatus::Waiting,
+                "in_progress" => GameStatus::InProgress,
+                "game_over" => GameStatus::Over(result_from_json(&reply)?),
+                _ => return None,
+            };
+            Some(Reply::Board(BoardState {
+                board: str_field(&reply, "position")?.parse::<Board>().ok()?,
+                turn: str_field(&reply, "turn")?.parse::<Colour>().ok()?,
+                move_number: reply.get("move_number").and_then(Value::as_u64)? as u32,
+                status: status,
+            }))
+        },
+        "moves" => {
+            let moves = reply.get("moves").and_then(Value::as_array)?;
+            let moves = moves.iter().map(move_from_json).collect::<Option<Vec<_>>>()?;
+            Some(Reply::Moves(moves))
+        },
+        "hello" => {
+            let version = reply.get("version").and_then(Value::as_u64)?;
+            //Capabilities from newer servers are ignored
+            let capabilities = reply.get("capabilities").and_then(Value::as_array)?.iter()
+                .filter_map(|c| c.as_str().and_then(|c| c.parse::<Capability>().ok()))
+                .collect();
+            Some(Reply::Hello(Handshake { version: version as u32, capabilities: capabilities }))
+        },
+        "error" => {
+            let code = str_field(&reply, "error")?;
+            let err = match parse_error_code(code) {
+                Some(err) => err,
+                None if code == "other" => {
+                    let message = str_field(&reply, "message").unwrap_or("");
+                    CommandErr::Other(message.trim_start_matches("Something went wrong: ").to_string())
+                },
+                None => return None,
+            };
+            Some(Reply::Rejected(err))
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::command::{PROTOCOL_VERSION,every_command_err};
+
+    #[test]
+    fn requests_round_trip() {
+        let requests = vec![
+            CommandData::Register("Bob".to_string(), 3),
+            CommandData::Ready(3),
+            CommandData::Move(3, Move::new(1, 0, 1, 2)),
+            CommandData::GetMove(3),
+            CommandData::Message(3, "\"Hi\", she said;\n{}".to_string()),
+            CommandData::Resign(3),
+            CommandData::OfferDraw(3),
+            CommandData::AcceptDraw(3),
+            CommandData::DeclineDraw(3),
+            CommandData::GetBoard(3),
+            CommandData::GetLegalMoves(3, None),
+            CommandData::GetLegalMoves(3, Some((0, 7))),
+            CommandData::Hello(3, PROTOCOL_VERSION),
+        ];
+        for request in requests {
+            let line = format_request(&request);
+            assert!(line.ends_with('\n') && !line.trim_end().contains('\n'));
+            let parsed = parse_request(&line, 3).expect(&line);
+            assert_eq!(format!("{:?}", parsed), format!("{:?}", request));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        for msg in &["", "[]", "{}", r#"{"type":"dance"}"#, r#"{"type":"register","name":"B b"}"#,
+                     r#"{"type":"move","from":[0,0]}"#, r#"{"type":"move","from":[0],"to":[1,1]}"#] {
+            assert!(parse_request(msg, 0).is_none(), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        let mov = Move::new(2, 0, 2, 3);
+        let win = GameResult::Win(Colour::White, WinReason::Timeout);
+        let ready = CommandData::Ready(0);
+        let get_move = CommandData::GetMove(0);
+
+        match parse_reply(&format_response(&ready, &Ok(CommandOk::Ready("Alice".to_string(), Colour::Black)))) {
+            Some(Reply::Start(Colour::Black, ref name)) if name == "Alice" => (),
+            reply => panic!("{:?}", reply),
+        }
+        match parse_reply(&format_response(&get_move, &Ok(CommandOk::Move(mov.clone())))) {
+            Some(Reply::Move(ref m)) if *m == mov => (),
+            reply => panic!("{:?}", reply),
+        }
+        for result in &[win, GameResult::Draw] {
+            match parse_reply(&format_response(&get_move, &Ok(CommandOk::GameOver(*result)))) {
+                Some(Reply::GameOver(r)) if r == *result => (),
+                reply => panic!("{:?}", reply),
+            }
+        }
+        let state = BoardState { board: Board::new(), turn: Colour::White, move_number: 5,
+                                 status: GameStatus::Over(win) };
+        match parse_reply(&format_response(&CommandData::GetBoard(0), &Ok(CommandOk::Board(state)))) {
+            Some(Reply::Board(ref s)) => {
+                assert_eq!(s.board.position(), Board::new().position());
+                assert_eq!((s.turn, s.move_number, s.status), (Colour::White, 5, GameStatus::Over(win)));
+            },
+            reply => panic!("{:?}", reply),
+        }
+        let moves = vec![mov.clone(), Move::new(0, 1, 3, 1)];
+        match parse_reply(&format_response(&CommandData::GetLegalMoves(0, None), &Ok(CommandOk::Moves(moves.clone())))) {
+            Some(Reply::Moves(ref m)) if *m == moves => (),
+            reply => panic!("{:?}", reply),
+        }
+        let handshake = Handshake { version: PROTOCOL_VERSION, capabilities: vec![Capability::Json] };
+        match parse_reply(&format_response(&CommandData::Hello(0, 1), &Ok(CommandOk::Hello(handshake.clone())))) {
+            Some(Reply::Hello(ref h)) if *h == handshake => (),
+            reply => panic!("{:?}", reply),
+        }
+        match parse_reply(&format_response(&ready, &Ok(CommandOk::None))) {
+            Some(Reply::Ok) => (),
+            reply => panic!("{:?}", reply),
+        }
+        for err in every_command_err() {
+            match parse_reply(&format_response(&get_move, &Err(err.clone()))) {
+                Some(Reply::Rejected(ref e)) if *e == err => (),
+                reply => panic!("{:?}", reply),
+            }
+        }
+    }
+
+    #[test]
+    fn events_round_trip() {
+        let board = Board::new();
+        match parse_reply(&format_event(&Event::Position(board.clone()))) {
+            Some(Reply::Event(Event::Position(ref b))) => assert_eq!(b.position(), board.position()),
+            reply => panic!("{:?}", reply),
+        }
+        let mov = Move::new(7, 1, 5, 1);
+        match parse_reply(&format_event(&Event::Move(mov.clone()))) {
+            Some(Reply::Event(Event::Move(ref m))) if *m == mov => (),
+            reply => panic!("{:?}", reply),
+        }
+        let result = GameResult::Win(Colour::Black, WinReason::Connection);
+        match parse_reply(&format_event(&Event::GameOver(result))) {
+            Some(Reply::Event(Event::GameOver(r))) if r == result => (),
+            reply => panic!("{:?}", reply),
+        }
+    }
+}
@@ -2,8 +2,17 @@
 extern crate log;
 
 pub mod board;
+#[cfg(feature="gui")]
 pub mod draw;
 pub mod server;
 pub mod command;
 pub mod player;
 pub mod api;
+pub mod clock;
+pub mod ai;
+pub mod tui;
+pub mod diagram;
+pub mod theme;
+pub mod record;
+pub mod replay;
+mod json;
@@ -1,31 +1,157 @@
+#[cfg(feature="gui")]
 extern crate sfml;
 extern crate lines_of_action;
 
 #[macro_use] extern crate log;
 extern crate env_logger;
+extern crate getopts;
+extern crate ctrlc;
 
 use std::collections::btree_map::BTreeMap;
+use std::cmp;
+use std::env;
+use std::process;
+use std::net::{SocketAddr,IpAddr,Ipv4Addr,Ipv6Addr};
+use std::path::{Path,PathBuf};
+use std::fs;
+use std::str::FromStr;
+use std::thread;
 
-use lines_of_action::board::{Board,SCREEN_SIZE,Colour,MoveErr};
-use lines_of_action::draw::Drawable;
-use lines_of_action::server::GameServer;
-use lines_of_action::command::{GetCommandErr,CommandProducer,CommandData,CommandErr,CommandResponse,CommandOk,Move};
+use getopts::{Options,Matches};
+
+use lines_of_action::board::{Board,Colour,MoveErr,Variant};
+use lines_of_action::clock::{Clocks,TimeControl};
+use lines_of_action::theme::Theme;
+use lines_of_action::record::{GameRecord,Date};
+use lines_of_action::replay::Replay;
+use lines_of_action::ai::{self,Strategy};
+use lines_of_action::server::{GameServer,DEFAULT_PORT,DEFAULT_WEBSOCKET_PORT};
+use lines_of_action::command::{GetCommandErr,CommandProducer,Command,CommandData,CommandErr,CommandResponse,CommandOk,Move,Event,GameResult,WinReason,GameStatus,BoardState,Handshake,Capability,PROTOCOL_VERSION};
 use lines_of_action::player::{Player,PlayerState};
 
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{channel,Sender,Receiver};
+
+use std::time::{Duration,Instant,SystemTime,UNIX_EPOCH};
+use std::fmt::{self,Formatter,Display};
+
+#[cfg(feature="gui")]
+mod gui;
+mod terminal;
 
-use sfml::window::{ContextSettings, VideoMode, event, Close};
-use sfml::window::keyboard::Key;
-use sfml::graphics::{RenderWindow, RenderTarget, Color};
+//How often to update a view while waiting for commands
+const FRAME_MS: u64 = 16;
+//How long moves are held for in `Playback::Delay` unless told otherwise
+const DEFAULT_DELAY_MS: u64 = 1000;
+//How long moves take to slide into place in the window unless told otherwise
+const DEFAULT_ANIMATION_MS: u64 = 250;
+//Where records of games go unless told otherwise
+const DEFAULT_RECORD_DIR: &'static str = "games";
+
+//Something showing the game
+trait View {
+    //Handles input and redraws. Returns false once the view has been closed.
+    fn update(&mut self, engine: &mut Engine) -> bool;
+}
 
 struct Engine {
     players: BTreeMap<i32,Player>,
+    spectators: BTreeMap<i32,Sender<Event>>,
     board: Board,
+    start: Board,
+    moves: Vec<Move>,
     server: GameServer,
-    window: RenderWindow,
     turn: Colour,
     game_started: bool,
-    send_moves: bool,
+    result: Option<GameResult>,
+    draw_offer: Option<i32>,
+    //A player who gets the given colour, whatever order the players connected in
+    reserved: Option<(i32,Colour)>,
+    playback: Playback,
+    //How long moves are held for in `Playback::Delay`
+    delay: Duration,
+    //When the move waiting to be passed on was made
+    moved_at: Instant,
+    //Whether the next move should be passed on in `Playback::Step`
+    step_requested: bool,
+    variant: Variant,
+    clocks: Option<Clocks>,
+    //Where to write the record of the game once it's over, or when the server stops
+    record: Option<RecordTo>,
+    //Set once the server has been told to quit
+    shutting_down: bool,
+}
+
+//Where to write records of games
+#[derive(Clone,Debug)]
+enum RecordTo {
+    File(PathBuf),
+    //A file for each game, named after when it finished and who played
+    Directory(PathBuf),
+}
+
+//How the server was asked to run
+struct Config {
+    addr: SocketAddr,
+    websocket_addr: SocketAddr,
+    variant: Variant,
+    //Overrides the variant's starting position
+    position: Option<Board>,
+    time_control: Option<TimeControl>,
+    ai: Option<Strategy>,
+    record: Option<RecordTo>,
+    log_level: Option<String>,
+    headless: bool,
+    //Show the game in the terminal instead of a window
+    tui: bool,
+    playback: Playback,
+    delay: Duration,
+    //Someone playing in the window, and their side
+    #[cfg_attr(not(feature="gui"), allow(dead_code))]
+    human: Option<(String,Colour)>,
+    //For writing in the window
+    #[cfg_attr(not(feature="gui"), allow(dead_code))]
+    font: Option<PathBuf>,
+    #[cfg_attr(not(feature="gui"), allow(dead_code))]
+    theme: Theme,
+    //How long moves take to slide into place in the window
+    #[cfg_attr(not(feature="gui"), allow(dead_code))]
+    animation: Duration,
+    //A recorded game to show instead of running the server
+    replay: Option<Replay>,
+}
+
+//When moves are passed on to the opponent
+#[derive(Clone,Copy,Debug,PartialEq)]
+enum Playback {
+    //As soon as they're made
+    Automatic,
+    //One at a time when someone presses space
+    Step,
+    //Once they've been on the board for a while
+    Delay,
+}
+
+impl Display for Playback {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Playback::Automatic => "automatic",
+            Playback::Step => "step",
+            Playback::Delay => "delay",
+        })
+    }
+}
+
+impl FromStr for Playback {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Playback, ()> {
+        match s {
+            "automatic" => Ok(Playback::Automatic),
+            "step" => Ok(Playback::Step),
+            "delay" => Ok(Playback::Delay),
+            _ => Err(()),
+        }
+    }
 }
 
 impl Engine {
@@ -38,161 +164,301 @@ impl Engine {
         return None;
     }
     
-    fn new() -> Engine {
-        let mut window = match RenderWindow::new(VideoMode::new_init(SCREEN_SIZE, SCREEN_SIZE, 32),
-                                                 "Lines of Action",
-                                                 Close,
-                                                 &ContextSettings::default()) {
-            Some(window) => window,
-            None => panic!("Cannot create a new Render Window.")
-        };
-
-        let mut board = Board::new();
-        let mut players = BTreeMap::new();
-        let server = GameServer::new();
+    fn new(server: GameServer, config: &Config) -> Engine {
+        let board = config.position.clone().unwrap_or(Board::new_variant(config.variant));
+        let players = BTreeMap::new();
 
         Engine {
             players: players,
+            spectators: BTreeMap::new(),
+            start: board.clone(),
             board: board,
+            moves: Vec::new(),
             server: server,
-            window: window,
-            turn: Colour::White,
+            turn: Colour::Black,
             game_started: false,
-            send_moves: false,
+            result: None,
+            draw_offer: None,
+            reserved: None,
+            playback: config.playback,
+            delay: config.delay,
+            moved_at: Instant::now(),
+            step_requested: false,
+            variant: config.variant,
+            clocks: config.time_control.map(Clocks::new),
+            record: config.record.clone(),
+            shutting_down: false,
         }
     }
 
-    fn run(&mut self){
-        // Create the window of the application
-        while self.window.is_open() {
-            // Handle events
-            for event in self.window.events() {
-                match event {
-                    event::Closed => self.window.close(),
-                    event::KeyPressed{code: c,..} => self.handle_key_press(c),
-                    _             => {/* do nothing */}
+    //Serves the game until the view is closed, or forever without one
+    fn run(&mut self, mut view: Option<Box<dyn View>>) {
+        while !self.shutting_down {
+            if let Some(ref mut view) = view {
+                if !view.update(self) {
+                    break;
                 }
             }
 
-            match self.server.get_command() {
-                Ok(mut command) =>
-                {
-                    match command.data {
-                        CommandData::Move(id,mov) =>
-                        self.move_piece(id,&mut command.reply,&mov),
-                        CommandData::GetMove(id) =>
-                        self.get_move(id, &mut command.reply),
-                        CommandData::Register(reg,id) =>
-                        self.register_player(&mut command.reply,&reg,id),
-                        CommandData::Message(id,msg) =>
-                        self.display_message(&mut command.reply,&msg),
-                        CommandData::Ready(id) =>
-                        self.handle_ready(&mut command.reply,id),
-                            //_ => Err("Unhandled command".to_string()),
-                    }
-                },
-
-                Err(GetCommandErr::Failed(why)) => panic!(why),
-                Err(GetCommandErr::NoCommands) => (),
-            }
+            //Without a view there's nothing to do until a command comes in, a held move can
+            //be passed on or a flag falls
+            let timeout = view.as_ref().map(|_| Duration::from_millis(FRAME_MS));
+            self.serve(timeout);
+        }
+
+        //A game cut short is still worth keeping, with no result
+        if self.game_started && self.result.is_none() {
+            info!("Stopping mid-game");
+            self.write_record();
+        }
+    }
+
+    //Handles a command if one comes within `timeout`, or before there's something else to do,
+    //then does whatever has come due
+    fn serve(&mut self, timeout: Option<Duration>) {
+        let timeout = match (timeout, self.idle_time()) {
+            (Some(timeout), Some(idle)) => Some(cmp::min(timeout, idle)),
+            (timeout, idle) => timeout.or(idle),
+        };
 
-            // Clear the window
-            let background = Color::new_rgb(75,45,25);
-            self.window.clear(&background);
+        match self.server.wait_for_command(timeout) {
+            Ok(command) => self.handle_command(command),
+            Err(GetCommandErr::Failed(why)) => panic!(why),
+            Err(GetCommandErr::NoCommands) => (),
+        }
 
-            match self.board.draw(&mut self.window) {
-                Err(s) => panic!(s),
-                Ok(()) => {},
-            };
+        self.check_clocks();
+        self.release_moves();
+    }
 
-            // Display things on screen
-            self.window.display();
+    //Replies only fail to send once their connection has gone, and a Leave for it follows,
+    //so the results of sending them are ignored throughout
+    fn handle_command(&mut self, mut command: Command) {
+        match command.data {
+            CommandData::Move(id,mov) =>
+            self.move_piece(id,&mut command.reply,&mov),
+            CommandData::GetMove(id) =>
+            self.get_move(id, &mut command.reply),
+            CommandData::Register(reg,id) =>
+            self.register_player(&mut command.reply,&reg,id),
+            CommandData::Message(id,msg) =>
+            self.display_message(&mut command.reply,&msg),
+            CommandData::Ready(id) =>
+            self.handle_ready(&mut command.reply,id),
+            CommandData::Resign(id) =>
+            self.resign(&mut command.reply,id),
+            CommandData::OfferDraw(id) =>
+            self.offer_draw(&mut command.reply,id),
+            CommandData::AcceptDraw(id) =>
+            self.accept_draw(&mut command.reply,id),
+            CommandData::DeclineDraw(id) =>
+            self.decline_draw(&mut command.reply,id),
+            CommandData::Leave(id) =>
+            self.leave(id),
+            CommandData::GetBoard(_) =>
+            self.get_board(&mut command.reply),
+            CommandData::GetLegalMoves(_,cell) =>
+            self.get_legal_moves(&mut command.reply,cell),
+            CommandData::Hello(_,version) =>
+            self.hello(&mut command.reply,version),
+            CommandData::Shutdown => {
+                self.shutting_down = true;
+                let _ = command.reply.send(Ok(CommandOk::None));
+            },
         }
     }
 
     fn get_move (&mut self, id: i32, reply: &mut Sender<CommandResponse>)
     {
-        let ref mut player = self.players.get_mut(&id).unwrap();
+        let ref mut player = match self.players.get_mut(&id) {
+            Some(player) => player,
+            None => { let _ = reply.send(Err(CommandErr::NotRegistered)); return; },
+        };
         debug!("{} requested a move.", player.name.clone());
 
-        if let Some(mov) = player.move_cache.clone() {
-            debug!("{} already has a move waiting.", player.name.clone());
-            if self.send_moves {
-                debug!("Sending move.");
-                reply.send(Ok(CommandOk::Move(mov))).unwrap();
-                self.send_moves = false;
-                player.move_cache = None;
-            } else {
-                debug!("{} waiting on move.", player.name.clone());
-                player.reply = Some(reply.clone());
-                player.state = PlayerState::WaitingOnMove;
-            }
+        if let Some(notice) = player.notices.pop_front() {
+            debug!("Sending notice to {}.", player.name.clone());
+            let _ = reply.send(Ok(notice));
         } else {
             debug!("{} waiting on move.", player.name.clone());
             player.reply = Some(reply.clone());
             player.state = PlayerState::WaitingOnMove;
+            self.release_moves();
         }
     }
     
     fn move_piece (&mut self, id: i32, reply: &mut Sender<CommandResponse>, mov: &Move) {
-        debug!("{} sent a move.", self.players.get(&id).unwrap().name);
-        let player_colour = self.players.get(&id).unwrap().colour;
+        let player_colour = match self.players.get(&id) {
+            Some(player) => { debug!("{} sent a move.", player.name); player.colour },
+            None => { let _ = reply.send(Err(CommandErr::NotRegistered)); return; },
+        };
 
-        reply.send(match self.board.move_piece(&mov,&player_colour) {
-            Ok(()) => {
-                Ok(CommandOk::None)
-            },
-            Err(MoveErr::NoPiece) => Err(CommandErr::NoPiece),
-            Err(MoveErr::WrongPiece) => Err(CommandErr::WrongPiece),
-        });
+        if let Some(result) = self.result {
+            let _ = reply.send(Ok(CommandOk::GameOver(result)));
+            return;
+        } else if !self.game_started {
+            let _ = reply.send(Err(CommandErr::NotStarted));
+            return;
+        } else if player_colour != self.turn {
+            let _ = reply.send(Err(CommandErr::NotYourTurn));
+            return;
+        }
 
-        let mut send_moves = self.send_moves.clone();
-        {
-            let ref mut other = self.get_other_player_mut(id).unwrap();
-            if let PlayerState::WaitingOnMove = other.state {
-                if send_moves {
-                    debug!("{} is waiting on move.", other.name.clone());
-                    debug!("Sending move.");
-                    other.reply.clone().unwrap().send(Ok(CommandOk::Move(mov.clone())));
-                    other.state = PlayerState::Default;
-                    send_moves = false;
-                } else {
-                    debug!("Caching move");
-                    other.move_cache = Some(mov.clone());
-                }
-            } else {
-                debug!("Caching move");
-                other.move_cache = Some(mov.clone());
+        //A move made after the flag fell is too late
+        if self.check_clocks() {
+            let _ = reply.send(Ok(CommandOk::GameOver(self.result.unwrap())));
+            return;
+        }
+
+        match self.board.move_piece(&mov,&player_colour) {
+            Ok(()) => {},
+            Err(MoveErr::NoPiece) => { let _ = reply.send(Err(CommandErr::NoPiece)); return; },
+            Err(MoveErr::WrongPiece) => { let _ = reply.send(Err(CommandErr::WrongPiece)); return; },
+            Err(MoveErr::IllegalMove) => { let _ = reply.send(Err(CommandErr::IllegalMove)); return; },
+        }
+
+        self.moves.push(mov.clone());
+        //An offer not taken up before the next move lapses, so don't tell anyone about it now
+        if self.draw_offer.take().is_some() {
+            for player in self.players.values_mut() {
+                player.notices.retain(|notice| match *notice { CommandOk::DrawOffered => false, _ => true });
             }
         }
-        self.send_moves = send_moves;
+        self.turn = self.turn.other();
+        if let Some(ref mut clocks) = self.clocks {
+            clocks.switch();
+        }
+        self.broadcast(Event::Move(mov.clone()));
+
+        if let Some(other) = self.get_other_player_mut(id) {
+            debug!("Caching move");
+            other.move_cache = Some(mov.clone());
+        }
+        self.moved_at = Instant::now();
+
+        match self.board.winner(player_colour) {
+            Some(winner) => {
+                //Make sure the loser gets to see the final move before hearing about it
+                let other = self.players.keys().cloned().find(|&i| i != id).unwrap();
+                if let Some(mov) = self.players.get_mut(&other).unwrap().move_cache.take() {
+                    self.notify(other, CommandOk::Move(mov));
+                }
+
+                let result = GameResult::Win(winner, WinReason::Connection);
+                self.end_game(result);
+                let _ = reply.send(Ok(CommandOk::GameOver(result)));
+            },
+            None => {
+                self.release_moves();
+                let _ = reply.send(Ok(CommandOk::None));
+            },
+        }
     }
     
     fn register_player(&mut self, reply: &mut Sender<CommandResponse>, name: &str, id: i32) {
-        if self.players.contains_key(&id) {
-            reply.send(Err(CommandErr::AlreadyRegistered));
+        if self.players.contains_key(&id) || self.spectators.contains_key(&id) {
+            let _ = reply.send(Err(CommandErr::AlreadyRegistered));
             return;
         }
 
-        reply.send(
+        let _ = reply.send(
             if self.players.len() < 2 {
                 self.players.insert(id,(Player::new(name.to_string())));
                 debug!("Registered new player: {}", name);
                 Ok(CommandOk::None)
             } else {
-                Err(CommandErr::GameFull)
+                debug!("Registered new spectator: {}", name);
+                Ok(CommandOk::Spectating(self.add_spectator(id)))
             });
     }
 
+    //Catches a new spectator up on the game so far and subscribes them to further events
+    fn add_spectator(&mut self, id: i32) -> Receiver<Event> {
+        let (send,recv) = channel();
+        let _ = send.send(Event::Position(self.start.clone()));
+        for mov in &self.moves {
+            let _ = send.send(Event::Move(mov.clone()));
+        }
+        self.spectators.insert(id, send);
+        recv
+    }
+
+    //Sends an event to all spectators, dropping any who have gone away
+    fn broadcast(&mut self, event: Event) {
+        let gone = self.spectators.iter()
+            .filter(|&(_, s)| s.send(event.clone()).is_err())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        for id in gone {
+            debug!("Spectator {} left.", id);
+            self.spectators.remove(&id);
+        }
+    }
+
+    fn get_board(&self, reply: &mut Sender<CommandResponse>) {
+        let status = match self.result {
+            Some(result) => GameStatus::Over(result),
+            None if self.game_started => GameStatus::InProgress,
+            None => GameStatus::Waiting,
+        };
+
+        let _ = reply.send(Ok(CommandOk::Board(BoardState {
+            board: self.board.clone(),
+            turn: self.turn,
+            move_number: self.moves.len() as u32 + 1,
+            status: status,
+        })));
+    }
+
+    fn get_legal_moves(&self, reply: &mut Sender<CommandResponse>, cell: Option<(u8,u8)>) {
+        let moves = if self.result.is_some() {
+            Vec::new()
+        } else {
+            match cell {
+                Some((x,y)) => self.board.legal_moves_from(x,y),
+                None => self.board.legal_moves(self.turn),
+            }
+        };
+
+        let _ = reply.send(Ok(CommandOk::Moves(moves)));
+    }
+
+    fn hello(&self, reply: &mut Sender<CommandResponse>, version: u32) {
+        let mut capabilities = vec![Capability::Json];
+        capabilities.extend(Variant::all().iter().map(|variant| Capability::Variant(variant.to_string())));
+        if self.clocks.is_some() {
+            capabilities.push(Capability::Clocks);
+        }
+
+        let _ = reply.send(Ok(CommandOk::Hello(Handshake {
+            version: cmp::min(version, PROTOCOL_VERSION),
+            capabilities: capabilities,
+        })));
+    }
+
     fn display_message(&self, reply: &mut Sender<CommandResponse>, msg: &str) {
         println!("{}",msg);
-        reply.send(Ok(CommandOk::None));
+        let _ = reply.send(Ok(CommandOk::None));
     }
 
     fn handle_ready(&mut self, reply: &mut Sender<CommandResponse>, id: i32) {
+        if let Some(result) = self.result {
+            let _ = reply.send(Ok(CommandOk::GameOver(result)));
+            return;
+        }
+
         match self.players.get_mut(&id) {
             Some(player) => player.ready = true,
-            None => { reply.send(Err(CommandErr::NotRegistered)); return; },
+            None => { let _ = reply.send(Err(CommandErr::NotRegistered)); return; },
+        }
+
+        //Just remind them if they've already started
+        if self.game_started {
+            let colour = self.players.get(&id).unwrap().colour;
+            let opponent = self.players.values().find(|p| p.colour != colour).unwrap().name.clone();
+            let _ = reply.send(Ok(CommandOk::Ready(opponent, colour)));
+            return;
         }
 
         self.players.get_mut(&id).unwrap().reply = Some(reply.clone());
@@ -208,51 +474,782 @@ impl Engine {
         }
 
         debug!("Ready to play!");
+        self.game_started = true;
+        if let Some(ref mut clocks) = self.clocks {
+            clocks.start(self.turn);
+        }
         
+        //The first to connect is White, unless the other player reserved it
         let keys = self.players.keys().cloned().collect::<Vec<_>>();
-        
-        {
-            let p2_name = self.players.get(&keys[1]).unwrap().name.clone();
-            let ref mut p1 = &mut self.players.get_mut(&keys[0]).unwrap();
-            p1.colour = Colour::White;
-            p1.reply.clone().unwrap().send(Ok(CommandOk::Ready(p2_name, p1.colour.clone())));
-        }
-
-        {
-            let p1_name = self.players.get(&keys[0]).unwrap().name.clone();
-            let ref mut p2 = &mut self.players.get_mut(&keys[1]).unwrap();
-            p2.colour = Colour::Black;
-            p2.reply.clone().unwrap().send(Ok(CommandOk::Ready(p1_name, p2.colour)));
-        }
-    }
-
-    fn handle_key_press(&mut self, code: Key) {
-        debug!("Key pressed");
-        if code == Key::Space {
-            debug!("It was space, checking available moves");
-            for i in self.players.keys().cloned().collect::<Vec<_>>() {
-                debug!("Checking player {}", i);
-                if let PlayerState::WaitingOnMove = self.players.get(&i).unwrap().state.clone() {
-                    debug!("They are waiting on a move");
-                    if let Some(mov) = self.players.get(&i).unwrap().move_cache.clone() {
-                        debug!("Sending move.");
-                        self.players.get(&i).unwrap().reply.clone().unwrap().send(Ok(CommandOk::Move(mov.clone())));
-                        self.players.get_mut(&i).unwrap().state = PlayerState::Default;
-                        self.players.get_mut(&i).unwrap().move_cache = None;
-                        self.send_moves = false;
-                        
-                        return;
-                    }
+        let white = match self.reserved {
+            Some((id, colour)) if keys.contains(&id) =>
+                if colour == Colour::White { id } else { *keys.iter().find(|&&k| k != id).unwrap() },
+            _ => keys[0],
+        };
+        let black = *keys.iter().find(|&&k| k != white).unwrap();
+
+        for &(id, colour, opponent) in &[(white, Colour::White, black), (black, Colour::Black, white)] {
+            let opponent_name = self.players.get(&opponent).unwrap().name.clone();
+            let player = self.players.get_mut(&id).unwrap();
+            player.colour = colour;
+            let _ = player.reply.clone().unwrap().send(Ok(CommandOk::Ready(opponent_name, colour)));
+        }
+    }
+
+    //Gives the player `id` the colour `colour` when the game starts
+    #[cfg(feature="gui")]
+    fn reserve_colour(&mut self, id: i32, colour: Colour) {
+        self.reserved = Some((id, colour));
+    }
+
+    //Tells a player something as soon as they ask for a move
+    fn notify(&mut self, id: i32, notice: CommandOk) {
+        let ref mut player = self.players.get_mut(&id).unwrap();
+        if let PlayerState::WaitingOnMove = player.state {
+            debug!("Sending notice to {}.", player.name.clone());
+            let _ = player.reply.clone().unwrap().send(Ok(notice));
+            player.state = PlayerState::Default;
+        } else {
+            player.notices.push_back(notice);
+        }
+    }
+
+    fn end_game(&mut self, result: GameResult) {
+        debug!("Game over, {}", result);
+        self.result = Some(result);
+        self.draw_offer = None;
+        if let Some(ref mut clocks) = self.clocks {
+            clocks.stop();
+        }
+
+        for id in self.players.keys().cloned().collect::<Vec<_>>() {
+            self.notify(id, CommandOk::GameOver(result));
+        }
+        self.broadcast(Event::GameOver(result));
+        self.write_record();
+    }
+
+    fn write_record(&self) {
+        if let Some(path) = self.record_path() {
+            let written = path.parent()
+                .map_or(Ok(()), |dir| fs::create_dir_all(dir))
+                .and_then(|()| self.game_record().save(&path));
+            match written {
+                Ok(()) => info!("Wrote the game to {}", path.display()),
+                Err(e) => error!("Couldn't write the game to {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    //Ends the game if the side to move has run out of time. Returns whether it has.
+    fn check_clocks(&mut self) -> bool {
+        if self.result.is_some() {
+            return false;
+        }
+
+        match self.clocks.as_ref().and_then(Clocks::flagged) {
+            Some(loser) => {
+                debug!("{:?} ran out of time.", loser);
+                self.end_game(GameResult::Win(loser.other(), WinReason::Timeout));
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn player_name(&self, colour: Colour) -> &str {
+        self.players.values()
+            .find(|p| p.colour == colour)
+            .map(|p| p.name.as_str())
+            .unwrap_or("?")
+    }
+
+    //The game so far
+    fn game_record(&self) -> GameRecord {
+        let mut record = GameRecord::new(self.player_name(Colour::Black), self.player_name(Colour::White), self.variant);
+        record.date = Some(Date::today());
+        record.start = self.start.clone();
+        record.time_control = self.clocks.as_ref().map(Clocks::time_control);
+        record.moves = self.moves.clone();
+        record.result = self.result;
+        record
+    }
+
+    //Where to write the record of the game, named after the time and players if it's going
+    //in a directory
+    fn record_path(&self) -> Option<PathBuf> {
+        match self.record {
+            Some(RecordTo::File(ref path)) => Some(path.clone()),
+            Some(RecordTo::Directory(ref dir)) => {
+                let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() % 86400;
+                //Names can be anything over the network, so keep them to what's safe in a file name
+                let clean = |name: &str| name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect::<String>();
+                Some(dir.join(format!("{}-{:02}{:02}{:02}-{}-{}.loa", Date::today(), secs / 3600, secs / 60 % 60, secs % 60,
+                                      clean(self.player_name(Colour::Black)), clean(self.player_name(Colour::White)))))
+            },
+            None => None,
+        }
+    }
+
+    //Checks that a player can do something which only makes sense mid-game
+    fn check_in_game(&self, reply: &mut Sender<CommandResponse>, id: i32) -> bool {
+        if !self.players.contains_key(&id) {
+            let _ = reply.send(Err(CommandErr::NotRegistered));
+        } else if let Some(result) = self.result {
+            let _ = reply.send(Ok(CommandOk::GameOver(result)));
+        } else if !self.game_started {
+            let _ = reply.send(Err(CommandErr::NotStarted));
+        } else {
+            return true;
+        }
+        false
+    }
+
+    fn leave(&mut self, id: i32) {
+        if self.spectators.remove(&id).is_some() {
+            debug!("Spectator {} left.", id);
+            return;
+        }
+
+        let colour = match self.players.get(&id) {
+            Some(player) => { debug!("{} left.", player.name); player.colour },
+            None => return,
+        };
+
+        if !self.game_started {
+            //Free up the space for someone else
+            self.players.remove(&id);
+        } else if self.result.is_none() {
+            self.end_game(GameResult::Win(colour.other(), WinReason::Disconnection));
+        }
+    }
+
+    fn resign(&mut self, reply: &mut Sender<CommandResponse>, id: i32) {
+        if !self.check_in_game(reply, id) {
+            return;
+        }
+
+        debug!("{} resigned.", self.players.get(&id).unwrap().name);
+        let result = GameResult::Win(self.players.get(&id).unwrap().colour.other(),
+                                     WinReason::Resignation);
+        self.end_game(result);
+        let _ = reply.send(Ok(CommandOk::GameOver(result)));
+    }
+
+    fn offer_draw(&mut self, reply: &mut Sender<CommandResponse>, id: i32) {
+        if !self.check_in_game(reply, id) {
+            return;
+        }
+
+        match self.draw_offer {
+            //Offering a draw to someone who has offered you one is agreeing to it
+            Some(offerer) if offerer != id => self.accept_draw(reply, id),
+            Some(_) => { let _ = reply.send(Ok(CommandOk::None)); },
+            None => {
+                debug!("{} offered a draw.", self.players.get(&id).unwrap().name);
+                self.draw_offer = Some(id);
+                let other = self.players.keys().cloned().find(|&i| i != id).unwrap();
+                self.notify(other, CommandOk::DrawOffered);
+                let _ = reply.send(Ok(CommandOk::None));
+            },
+        }
+    }
+
+    fn accept_draw(&mut self, reply: &mut Sender<CommandResponse>, id: i32) {
+        if !self.check_in_game(reply, id) {
+            return;
+        }
+
+        match self.draw_offer {
+            Some(offerer) if offerer != id => {
+                debug!("{} accepted a draw.", self.players.get(&id).unwrap().name);
+                self.end_game(GameResult::Draw);
+                let _ = reply.send(Ok(CommandOk::GameOver(GameResult::Draw)));
+            },
+            _ => { let _ = reply.send(Err(CommandErr::NoDrawOffered)); },
+        }
+    }
+
+    fn decline_draw(&mut self, reply: &mut Sender<CommandResponse>, id: i32) {
+        if !self.check_in_game(reply, id) {
+            return;
+        }
+
+        match self.draw_offer {
+            Some(offerer) if offerer != id => {
+                debug!("{} declined a draw.", self.players.get(&id).unwrap().name);
+                self.draw_offer = None;
+                self.notify(offerer, CommandOk::DrawDeclined);
+                let _ = reply.send(Ok(CommandOk::None));
+            },
+            _ => { let _ = reply.send(Err(CommandErr::NoDrawOffered)); },
+        }
+    }
+
+    //How long until the waiting move can be passed on, if it's being held up
+    fn release_delay(&self) -> Option<Duration> {
+        match self.playback {
+            Playback::Automatic => Some(Duration::from_secs(0)),
+            Playback::Step if self.step_requested => Some(Duration::from_secs(0)),
+            Playback::Step => None,
+            Playback::Delay => Some(self.delay.checked_sub(self.moved_at.elapsed())
+                                    .unwrap_or(Duration::from_secs(0))),
+        }
+    }
+
+    //Passes on the opponent's move to whoever is waiting for one, if playback allows it
+    fn release_moves(&mut self) {
+        if self.release_delay() != Some(Duration::from_secs(0)) {
+            return;
+        }
+
+        for player in self.players.values_mut() {
+            if let PlayerState::WaitingOnMove = player.state {
+                if let Some(mov) = player.move_cache.take() {
+                    debug!("Sending move to {}.", player.name);
+                    let _ = player.reply.clone().unwrap().send(Ok(CommandOk::Move(mov)));
+                    player.state = PlayerState::Default;
+                    self.step_requested = false;
                 }
             }
-            self.send_moves = true;
         }
     }
+
+    //Lets the next move through in `Playback::Step`
+    #[cfg(feature="gui")]
+    fn step(&mut self) {
+        debug!("Stepping");
+        self.step_requested = true;
+        self.release_moves();
+    }
+
+    #[cfg(feature="gui")]
+    fn set_playback(&mut self, playback: Playback) {
+        info!("Playback is now {}", playback);
+        self.playback = playback;
+        self.step_requested = false;
+        self.release_moves();
+    }
+
+    //How long the engine can wait for a command before it has something else to do
+    fn idle_time(&self) -> Option<Duration> {
+        let flag = self.clocks.as_ref()
+            .and_then(|clocks| clocks.running().map(|colour| clocks.remaining(colour)));
+        let waiting = self.players.values().any(|p| match p.state {
+            PlayerState::WaitingOnMove => p.move_cache.is_some(),
+            PlayerState::Default => false,
+        });
+        let release = if waiting { self.release_delay() } else { None };
+
+        match (flag, release) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+//Parses an option's value with `FromStr`, if it was given
+fn parse_opt<T: FromStr>(matches: &Matches, name: &str) -> Result<Option<T>,String> {
+    match matches.opt_str(name) {
+        Some(value) => value.parse::<T>()
+            .map(Some)
+            .map_err(|_| format!("Bad value for --{}: {}", name, value)),
+        None => Ok(None),
+    }
+}
+
+fn options() -> Options {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help");
+    opts.optopt("b", "bind", "address to listen on, IPv4 or IPv6 (default 127.0.0.1)", "ADDR");
+    opts.optopt("p", "port", &format!("port for TCP connections, 0 for any (default {})", DEFAULT_PORT), "PORT");
+    opts.optopt("w", "websocket-port",
+                &format!("port for WebSocket connections, 0 for any (default {})", DEFAULT_WEBSOCKET_PORT), "PORT");
+    opts.optopt("", "variant", "starting layout: standard or scrambled-eggs (default standard)", "NAME");
+    opts.optopt("", "position",
+                "starting position, as sent to spectators, e.g. .bbbbbb./w......w/... Overrides --variant",
+                "POSITION");
+    opts.optopt("t", "time", "give each side SECONDS for the game, plus INCREMENT per move", "SECONDS[+INCREMENT]");
+    opts.optopt("", "ai", "take one side with a computer player: first, random or greedy", "STRATEGY");
+    opts.optopt("r", "record", "write the record of the game to FILE once it's over or the server stops, instead of to --record-dir", "FILE");
+    opts.optopt("", "record-dir", &format!("write the record of the game to a new file in DIR once it's over or the server stops (default {})", DEFAULT_RECORD_DIR), "DIR");
+    opts.optflag("", "no-record", "don't write a record of the game");
+    opts.optflag("", "headless", "run without a window. Always on if built without the gui feature");
+    opts.optflag("", "tui", "show the game in the terminal instead of a window. Logging goes to stderr, so redirect it");
+    opts.optopt("", "playback", "when moves are passed on: automatic, step (when space is pressed) or delay (default automatic)", "MODE");
+    opts.optopt("", "delay", &format!("how long moves are held with --playback delay (default {})", DEFAULT_DELAY_MS), "MILLISECONDS");
+    opts.optopt("", "human", "play one side yourself by clicking in the window", "NAME");
+    opts.optopt("", "colour", "the side to play with --human: White or Black (default Black, who moves first)", "COLOUR");
+    opts.optopt("", "font", "TrueType font for writing in the window (default DejaVu Sans if it can be found)", "FILE");
+    opts.optopt("", "theme", "colours for the window, see the theme module's documentation", "FILE");
+    opts.optopt("", "animation", &format!("how long moves take to slide into place in the window, 0 not to animate them (default {})", DEFAULT_ANIMATION_MS), "MILLISECONDS");
+    opts.optopt("", "replay", "show a recorded game instead of running the server, in the window or with --headless in the terminal", "FILE");
+    opts.optopt("l", "log-level", "log level or filter, as for RUST_LOG (default from RUST_LOG)", "LEVEL");
+    opts
+}
+
+fn parse_config(matches: &Matches) -> Result<Config,String> {
+    let ip = parse_opt::<IpAddr>(matches, "bind")?.unwrap_or(IpAddr::V4(Ipv4Addr::new(127,0,0,1)));
+    let port = parse_opt::<u16>(matches, "port")?.unwrap_or(DEFAULT_PORT);
+    let websocket_port = parse_opt::<u16>(matches, "websocket-port")?.unwrap_or(DEFAULT_WEBSOCKET_PORT);
+    //There's no window to show without the gui feature
+    let tui = matches.opt_present("tui");
+    let headless = matches.opt_present("headless") || tui || !cfg!(feature="gui");
+    let playback = parse_opt::<Playback>(matches, "playback")?.unwrap_or(Playback::Automatic);
+    if headless && playback == Playback::Step {
+        return Err("Stepping through moves needs a window to press space in".to_string());
+    }
+
+    let human = match matches.opt_str("human") {
+        Some(ref name) if headless => return Err(format!("{} needs a window to play in", name)),
+        //Names are restricted so they can be sent to text clients
+        Some(ref name) if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) =>
+            return Err(format!("Bad value for --human: {}", name)),
+        Some(name) => Some((name, parse_opt::<Colour>(matches, "colour")?.unwrap_or(Colour::Black))),
+        None => None,
+    };
+
+    Ok(Config {
+        addr: SocketAddr::new(ip, port),
+        websocket_addr: SocketAddr::new(ip, websocket_port),
+        variant: parse_opt::<Variant>(matches, "variant")?.unwrap_or(Variant::Standard),
+        position: parse_opt::<Board>(matches, "position")?,
+        time_control: parse_opt::<TimeControl>(matches, "time")?,
+        ai: parse_opt::<Strategy>(matches, "ai")?,
+        record: if matches.opt_present("no-record") {
+            None
+        } else if let Some(path) = matches.opt_str("record") {
+            Some(RecordTo::File(PathBuf::from(path)))
+        } else {
+            Some(RecordTo::Directory(PathBuf::from(matches.opt_str("record-dir").unwrap_or(DEFAULT_RECORD_DIR.to_string()))))
+        },
+        log_level: matches.opt_str("log-level"),
+        headless: headless,
+        tui: tui,
+        playback: playback,
+        delay: Duration::from_millis(parse_opt::<u64>(matches, "delay")?.unwrap_or(DEFAULT_DELAY_MS)),
+        human: human,
+        font: matches.opt_str("font").map(PathBuf::from),
+        theme: match matches.opt_str("theme") {
+            Some(path) => Theme::load(Path::new(&path))?,
+            None => Theme::default(),
+        },
+        animation: Duration::from_millis(parse_opt::<u64>(matches, "animation")?.unwrap_or(DEFAULT_ANIMATION_MS)),
+        replay: match matches.opt_str("replay") {
+            Some(path) => Some(Replay::load(Path::new(&path))?),
+            None => None,
+        },
+    })
+}
+
+//Where to connect to reach a server listening on `addr`
+fn connect_addr(addr: SocketAddr) -> SocketAddr {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::new(127,0,0,1)),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::new(0,0,0,0,0,0,0,1)),
+        ip => ip,
+    };
+    SocketAddr::new(ip, addr.port())
 }
 
 fn main() {
+    let opts = options();
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let config = match opts.parse(&args).map_err(|e| e.to_string()) {
+        Ok(ref matches) if matches.opt_present("help") => {
+            print!("{}", opts.usage("Usage: loa-server [options]"));
+            return;
+        },
+        Ok(matches) => parse_config(&matches),
+        Err(why) => Err(why),
+    };
+    let mut config = match config {
+        Ok(config) => config,
+        Err(why) => {
+            eprintln!("{}\n{}", why, opts.short_usage("loa-server"));
+            process::exit(2);
+        },
+    };
+
+    //The logger only knows about RUST_LOG
+    if let Some(ref filter) = config.log_level {
+        env::set_var("RUST_LOG", filter);
+    }
     env_logger::init().unwrap();
-    
-    let mut engine = Engine::new();
-    engine.run();
+
+    if let Some(replay) = config.replay.take() {
+        show_replay(replay, &config);
+        return;
+    }
+
+    let server = match GameServer::new(config.addr, Some(config.websocket_addr)) {
+        Ok(server) => server,
+        Err(e) => { eprintln!("Couldn't start server: {}", e); process::exit(1); },
+    };
+    //Reported so that scripts can find a server started on port 0
+    println!("Listening on {}", server.addr());
+    if let Some(websocket_addr) = server.websocket_addr() {
+        println!("Listening for WebSockets on {}", websocket_addr);
+    }
+
+    if let Some(strategy) = config.ai {
+        let addr = connect_addr(server.addr());
+        thread::spawn(move || {
+            match ai::play(addr, "Computer", strategy) {
+                Ok(result) => info!("The computer's game is over, {}", result),
+                Err(e) => error!("The computer couldn't play: {}", e),
+            }
+        });
+    }
+
+    if config.headless {
+        info!("Running without a window, playback is {}", config.playback);
+    }
+
+    //Quitting with Ctrl-C or a signal lets the engine write out a game in progress first
+    let commands = server.command_sender();
+    let quit = move || {
+        let (reply, _) = channel();
+        let _ = commands.send(Command { data: CommandData::Shutdown, reply: reply });
+    };
+    if let Err(e) = ctrlc::set_handler(quit) {
+        error!("Couldn't handle Ctrl-C: {}", e);
+    }
+
+    let mut engine = Engine::new(server, &config);
+    let view = new_view(&mut engine, &config);
+    engine.run(view);
+}
+
+#[cfg(feature="gui")]
+fn new_view(engine: &mut Engine, config: &Config) -> Option<Box<dyn View>> {
+    if config.tui {
+        Some(Box::new(terminal::Terminal::new()))
+    } else if config.headless {
+        None
+    } else {
+        let font = config.font.as_ref().map(PathBuf::as_path);
+        Some(Box::new(gui::Gui::new(engine, config.human.as_ref(), font, config.theme.clone(), config.animation)))
+    }
+}
+
+#[cfg(not(feature="gui"))]
+fn new_view(_engine: &mut Engine, config: &Config) -> Option<Box<dyn View>> {
+    if config.tui {
+        Some(Box::new(terminal::Terminal::new()))
+    } else {
+        None
+    }
+}
+
+fn replay_in_terminal(replay: Replay) {
+    if let Err(e) = terminal::replay(replay) {
+        eprintln!("Couldn't show the game: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(feature="gui")]
+fn show_replay(replay: Replay, config: &Config) {
+    if config.headless {
+        replay_in_terminal(replay);
+    } else {
+        gui::replay(replay, config.font.as_ref().map(PathBuf::as_path), &config.theme);
+    }
+}
+
+#[cfg(not(feature="gui"))]
+fn show_replay(replay: Replay, _config: &Config) {
+    replay_in_terminal(replay);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A headless engine set up with `args`, and a way to send it commands
+    fn engine(args: &[&str]) -> (Engine, Sender<Command>) {
+        let mut args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        args.extend(["--headless", "--port", "0"].iter().map(|arg| arg.to_string()));
+        if !args.iter().any(|arg| arg.starts_with("--record")) {
+            args.push("--no-record".to_string());
+        }
+        let config = parse_config(&options().parse(&args).unwrap()).unwrap();
+        let server = GameServer::new(config.addr, None).unwrap();
+        let commands = server.command_sender();
+        (Engine::new(server, &config), commands)
+    }
+
+    //Has the engine handle a command, returning where its reply will go
+    fn send(engine: &mut Engine, commands: &Sender<Command>, data: CommandData) -> Receiver<CommandResponse> {
+        let (reply, replies) = channel();
+        commands.send(Command { data: data, reply: reply }).unwrap();
+        engine.serve(Some(Duration::from_millis(0)));
+        replies
+    }
+
+    //The engine's reply to a command which is answered straight away
+    fn request(engine: &mut Engine, commands: &Sender<Command>, data: CommandData) -> CommandResponse {
+        send(engine, commands, data).try_recv().unwrap()
+    }
+
+    //Starts a game between White, with id 1, and Black, with id 2
+    fn start(engine: &mut Engine, commands: &Sender<Command>) {
+        request(engine, commands, CommandData::Register("White".to_string(), 1)).unwrap();
+        request(engine, commands, CommandData::Register("Black".to_string(), 2)).unwrap();
+        let white = send(engine, commands, CommandData::Ready(1));
+        let black = send(engine, commands, CommandData::Ready(2));
+        assert!(match white.try_recv() { Ok(Ok(CommandOk::Ready(_, Colour::White))) => true, _ => false });
+        assert!(match black.try_recv() { Ok(Ok(CommandOk::Ready(_, Colour::Black))) => true, _ => false });
+    }
+
+    fn is_game_over(response: CommandResponse, expected: GameResult) -> bool {
+        match response {
+            Ok(CommandOk::GameOver(result)) => result == expected,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn spectators_catch_up_and_follow_the_game() {
+        let (mut engine, commands) = engine(&[]);
+        start(&mut engine, &commands);
+        request(&mut engine, &commands, CommandData::Move(2, Move::new(1,0,1,2))).unwrap();
+
+        let events = match request(&mut engine, &commands, CommandData::Register("Eve".to_string(), 3)) {
+            Ok(CommandOk::Spectating(events)) => events,
+            other => panic!("{:?}", other),
+        };
+        assert!(match events.try_recv() { Ok(Event::Position(ref board)) => board.position() == Board::new().position(), _ => false });
+        assert!(match events.try_recv() { Ok(Event::Move(mov)) => mov == Move::new(1,0,1,2), _ => false });
+        assert!(events.try_recv().is_err());
+
+        request(&mut engine, &commands, CommandData::Move(1, Move::new(0,1,2,1))).unwrap();
+        assert!(match events.try_recv() { Ok(Event::Move(mov)) => mov == Move::new(0,1,2,1), _ => false });
+        request(&mut engine, &commands, CommandData::Resign(1)).unwrap();
+        assert!(match events.try_recv() {
+            Ok(Event::GameOver(result)) => result == GameResult::Win(Colour::Black, WinReason::Resignation),
+            _ => false,
+        });
+
+        assert!(match request(&mut engine, &commands, CommandData::Register("Eve".to_string(), 3)) {
+            Err(CommandErr::AlreadyRegistered) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn resigning_loses_the_game() {
+        let (mut engine, commands) = engine(&[]);
+        assert!(match request(&mut engine, &commands, CommandData::Resign(1)) { Err(CommandErr::NotRegistered) => true, _ => false });
+        start(&mut engine, &commands);
+
+        //White hears about it while waiting for Black's move
+        let white = send(&mut engine, &commands, CommandData::GetMove(1));
+        let result = GameResult::Win(Colour::White, WinReason::Resignation);
+        assert!(is_game_over(request(&mut engine, &commands, CommandData::Resign(2)), result));
+        assert!(is_game_over(white.try_recv().unwrap(), result));
+
+        //And anything else either side tries is too late
+        assert!(is_game_over(request(&mut engine, &commands, CommandData::Move(2, Move::new(1,0,1,2))), result));
+        assert!(is_game_over(request(&mut engine, &commands, CommandData::Resign(1)), result));
+        assert!(is_game_over(request(&mut engine, &commands, CommandData::OfferDraw(1)), result));
+    }
+
+    #[test]
+    fn draws_can_be_offered_declined_and_accepted() {
+        let (mut engine, commands) = engine(&[]);
+        assert!(match request(&mut engine, &commands, CommandData::OfferDraw(1)) { Err(CommandErr::NotRegistered) => true, _ => false });
+        start(&mut engine, &commands);
+        assert!(match request(&mut engine, &commands, CommandData::AcceptDraw(2)) { Err(CommandErr::NoDrawOffered) => true, _ => false });
+
+        request(&mut engine, &commands, CommandData::OfferDraw(1)).unwrap();
+        assert!(match request(&mut engine, &commands, CommandData::GetMove(2)) { Ok(CommandOk::DrawOffered) => true, _ => false });
+        //You can't accept your own offer
+        assert!(match request(&mut engine, &commands, CommandData::AcceptDraw(1)) { Err(CommandErr::NoDrawOffered) => true, _ => false });
+        request(&mut engine, &commands, CommandData::DeclineDraw(2)).unwrap();
+        assert!(match request(&mut engine, &commands, CommandData::GetMove(1)) { Ok(CommandOk::DrawDeclined) => true, _ => false });
+        assert!(match request(&mut engine, &commands, CommandData::AcceptDraw(2)) { Err(CommandErr::NoDrawOffered) => true, _ => false });
+
+        request(&mut engine, &commands, CommandData::OfferDraw(2)).unwrap();
+        assert!(is_game_over(request(&mut engine, &commands, CommandData::AcceptDraw(1)), GameResult::Draw));
+        assert!(is_game_over(request(&mut engine, &commands, CommandData::GetMove(2)), GameResult::Draw));
+    }
+
+    #[test]
+    fn draw_offers_lapse_after_a_move() {
+        let (mut engine, commands) = engine(&[]);
+        start(&mut engine, &commands);
+        request(&mut engine, &commands, CommandData::OfferDraw(1)).unwrap();
+        request(&mut engine, &commands, CommandData::Move(2, Move::new(1,0,1,2))).unwrap();
+        assert!(match request(&mut engine, &commands, CommandData::AcceptDraw(2)) { Err(CommandErr::NoDrawOffered) => true, _ => false });
+        assert!(send(&mut engine, &commands, CommandData::GetMove(2)).try_recv().is_err());
+
+        //So offering one now is a new offer rather than agreeing to the old one
+        assert!(match request(&mut engine, &commands, CommandData::OfferDraw(2)) { Ok(CommandOk::None) => true, _ => false });
+    }
+
+    #[test]
+    fn offering_a_draw_back_accepts_it() {
+        let (mut engine, commands) = engine(&[]);
+        start(&mut engine, &commands);
+        request(&mut engine, &commands, CommandData::OfferDraw(1)).unwrap();
+        assert!(is_game_over(request(&mut engine, &commands, CommandData::OfferDraw(2)), GameResult::Draw));
+    }
+
+    #[test]
+    fn leaving_mid_game_forfeits_it() {
+        let (mut engine, commands) = engine(&[]);
+        request(&mut engine, &commands, CommandData::Register("Quitter".to_string(), 3)).unwrap();
+        //Before the game, it just frees up the space
+        send(&mut engine, &commands, CommandData::Leave(3));
+        start(&mut engine, &commands);
+
+        send(&mut engine, &commands, CommandData::Leave(2));
+        assert!(is_game_over(request(&mut engine, &commands, CommandData::GetMove(1)),
+                             GameResult::Win(Colour::White, WinReason::Disconnection)));
+    }
+
+    #[test]
+    fn moves_are_checked() {
+        let (mut engine, commands) = engine(&[]);
+        let mov = |engine: &mut Engine, id, mov| request(engine, &commands, CommandData::Move(id, mov));
+        assert!(match mov(&mut engine, 1, Move::new(1,0,1,2)) { Err(CommandErr::NotRegistered) => true, _ => false });
+        request(&mut engine, &commands, CommandData::Register("White".to_string(), 1)).unwrap();
+        assert!(match mov(&mut engine, 1, Move::new(1,0,1,2)) { Err(CommandErr::NotStarted) => true, _ => false });
+        send(&mut engine, &commands, CommandData::Leave(1));
+        start(&mut engine, &commands);
+
+        assert!(match mov(&mut engine, 1, Move::new(0,1,2,1)) { Err(CommandErr::NotYourTurn) => true, _ => false });
+        assert!(match mov(&mut engine, 2, Move::new(3,3,3,4)) { Err(CommandErr::NoPiece) => true, _ => false });
+        assert!(match mov(&mut engine, 2, Move::new(0,1,2,1)) { Err(CommandErr::WrongPiece) => true, _ => false });
+        assert!(match mov(&mut engine, 2, Move::new(1,0,1,5)) { Err(CommandErr::IllegalMove) => true, _ => false });
+        assert!(match mov(&mut engine, 2, Move::new(1,0,1,2)) { Ok(CommandOk::None) => true, _ => false });
+        assert!(match mov(&mut engine, 2, Move::new(2,0,2,2)) { Err(CommandErr::NotYourTurn) => true, _ => false });
+    }
+
+    #[test]
+    fn reports_the_state_of_the_game() {
+        let (mut engine, commands) = engine(&[]);
+        let state = |engine: &mut Engine| match request(engine, &commands, CommandData::GetBoard(7)) {
+            Ok(CommandOk::Board(state)) => state,
+            other => panic!("{:?}", other),
+        };
+
+        let before = state(&mut engine);
+        assert_eq!((before.turn, before.move_number, before.status), (Colour::Black, 1, GameStatus::Waiting));
+        start(&mut engine, &commands);
+        request(&mut engine, &commands, CommandData::Move(2, Move::new(1,0,1,2))).unwrap();
+
+        let after = state(&mut engine);
+        assert_eq!((after.turn, after.move_number, after.status), (Colour::White, 2, GameStatus::InProgress));
+        assert!(after.board.position().starts_with("..bbbbb./w......w/wb.....w/"));
+        request(&mut engine, &commands, CommandData::Resign(1)).unwrap();
+        assert_eq!(state(&mut engine).status, GameStatus::Over(GameResult::Win(Colour::Black, WinReason::Resignation)));
+    }
+
+    #[test]
+    fn says_hello_with_every_variant() {
+        let (mut engine, commands) = engine(&[]);
+        let handshake = match request(&mut engine, &commands, CommandData::Hello(7, 1)) {
+            Ok(CommandOk::Hello(handshake)) => handshake,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(handshake.version, 1);
+        for name in &["standard", "scrambled-eggs"] {
+            assert!(handshake.capabilities.contains(&Capability::Variant(name.to_string())), "{:?}", handshake);
+        }
+        assert!(!handshake.capabilities.contains(&Capability::Clocks));
+    }
+
+    #[test]
+    fn passes_moves_on_straight_away_without_a_window() {
+        let (mut engine, commands) = engine(&[]);
+        start(&mut engine, &commands);
+        let white = send(&mut engine, &commands, CommandData::GetMove(1));
+        assert!(white.try_recv().is_err());
+        request(&mut engine, &commands, CommandData::Move(2, Move::new(1,0,1,2))).unwrap();
+        assert!(match white.try_recv() { Ok(Ok(CommandOk::Move(mov))) => mov == Move::new(1,0,1,2), _ => false });
+    }
+
+    #[test]
+    fn holds_moves_until_a_step_is_asked_for() {
+        //Stepping needs a window to press space in, so there's no option for it here
+        let (mut engine, commands) = engine(&[]);
+        engine.playback = Playback::Step;
+        start(&mut engine, &commands);
+        let white = send(&mut engine, &commands, CommandData::GetMove(1));
+        request(&mut engine, &commands, CommandData::Move(2, Move::new(1,0,1,2))).unwrap();
+        assert!(white.try_recv().is_err());
+
+        engine.step_requested = true;
+        engine.serve(Some(Duration::from_millis(0)));
+        assert!(match white.try_recv() { Ok(Ok(CommandOk::Move(mov))) => mov == Move::new(1,0,1,2), _ => false });
+
+        //Each step lets one move through
+        let black = send(&mut engine, &commands, CommandData::GetMove(2));
+        request(&mut engine, &commands, CommandData::Move(1, Move::new(0,1,2,1))).unwrap();
+        engine.serve(Some(Duration::from_millis(0)));
+        assert!(black.try_recv().is_err());
+    }
+
+    #[test]
+    fn holds_moves_for_the_delay() {
+        let (mut engine, commands) = engine(&["--playback", "delay", "--delay", "50"]);
+        start(&mut engine, &commands);
+        let white = send(&mut engine, &commands, CommandData::GetMove(1));
+        request(&mut engine, &commands, CommandData::Move(2, Move::new(1,0,1,2))).unwrap();
+        assert!(white.try_recv().is_err());
+
+        //Nothing else is coming, so this returns once the move can be passed on
+        engine.serve(None);
+        assert!(engine.moved_at.elapsed() >= Duration::from_millis(50));
+        assert!(match white.try_recv() { Ok(Ok(CommandOk::Move(mov))) => mov == Move::new(1,0,1,2), _ => false });
+    }
+
+    #[test]
+    fn records_the_game_once_it_is_over() {
+        let path = env::temp_dir().join(format!("loa-record-{}.loa", process::id()));
+        let (mut engine, commands) = engine(&["--record", path.to_str().unwrap()]);
+        start(&mut engine, &commands);
+        request(&mut engine, &commands, CommandData::Move(2, Move::new(1,0,1,2))).unwrap();
+        assert!(!path.exists());
+
+        request(&mut engine, &commands, CommandData::Resign(1)).unwrap();
+        let record = GameRecord::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((record.black.as_str(), record.white.as_str()), ("Black", "White"));
+        assert_eq!(record.moves, vec![Move::new(1,0,1,2)]);
+        assert_eq!(record.result, Some(GameResult::Win(Colour::Black, WinReason::Resignation)));
+    }
+
+    #[test]
+    fn records_a_game_in_progress_when_shut_down() {
+        let path = env::temp_dir().join(format!("loa-unfinished-{}.loa", process::id()));
+        let (mut engine, commands) = engine(&["--record", path.to_str().unwrap()]);
+        start(&mut engine, &commands);
+        request(&mut engine, &commands, CommandData::Move(2, Move::new(1,0,1,2))).unwrap();
+
+        let (reply, _) = channel();
+        commands.send(Command { data: CommandData::Shutdown, reply: reply }).unwrap();
+        engine.run(None);
+        let record = GameRecord::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(record.moves, vec![Move::new(1,0,1,2)]);
+        assert_eq!(record.result, None);
+    }
+
+    #[test]
+    fn wakes_up_when_a_flag_falls() {
+        let (mut engine, commands) = engine(&[]);
+        engine.clocks = Some(Clocks::new(TimeControl { initial: Duration::from_millis(50), increment: Duration::from_secs(0) }));
+        start(&mut engine, &commands);
+        let black = send(&mut engine, &commands, CommandData::GetMove(2));
+
+        //Nothing else is coming, so this returns once Black's time is up
+        engine.serve(None);
+        let result = GameResult::Win(Colour::White, WinReason::Timeout);
+        assert!(is_game_over(black.try_recv().unwrap(), result));
+        assert!(is_game_over(request(&mut engine, &commands, CommandData::GetMove(1)), result));
+    }
 }
@@ -1,5 +1,6 @@
 use std::sync::mpsc::{Sender};
-use super::command::{CommandResponse,Move};
+use std::collections::VecDeque;
+use super::command::{CommandResponse,CommandOk,Move};
 use super::board::Colour;
 
 #[derive(Clone)]
@@ -14,6 +15,8 @@ pub struct Player {
     pub reply: Option<Sender<CommandResponse>>,
     pub colour: Colour,
     pub move_cache: Option<Move>,
+    ///Things to tell the player before their next move, e.g. draw offers
+    pub notices: VecDeque<CommandOk>,
     pub state: PlayerState,
 }
 
@@ -25,6 +28,7 @@ impl Player {
             reply: None,
             colour: Colour::White,
             move_cache: None,
+            notices: VecDeque::new(),
             state: PlayerState::Default,
         }
     }
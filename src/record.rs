@@ -0,0 +1,432 @@
+//! Records of whole games, in a format modelled on chess's PGN
+//!
+//! A record starts with tags, one to a line, giving the players, date, variant, result and
+//! time control, followed by the moves:
+//!
+//! ```text
+//! [Black "Alice"]
+//! [White "Bob"]
+//! [Date "2026.10.19"]
+//! [Variant "standard"]
+//! [TimeControl "300+5"]
+//! [Result "1-0"]
+//! [Termination "connection"]
+//!
+//! 1. 1,0-1,2 0,3-2,3 2. 6,7x6,5 ...
+//! 12. 4,0-4,4 1-0
+//! ```
+//!
+//! Tags are written `[Name "value"]`, with `\"` and `\\` for quotes and backslashes in the
+//! value, and `\n`, `\r` and `\t` for newlines, carriage returns and tabs. As well as those
+//! above there may be a `Position` tag with the starting position, in the notation of
+//! `Board::position`, when the game didn't start from the variant's usual layout. Other tags
+//! are kept, but mean nothing to the server.
+//!
+//! Black moves first, so Black is listed first and each numbered pair of moves is Black's then
+//! White's. A move is written `SX,SY-DX,DY`, with an `x` instead of the `-` if it captures.
+//! Text in `{braces}` is a comment. The moves end with the result: `1-0` if Black won, `0-1`
+//! if White won, `1/2-1/2` for a draw, or `*` if the game didn't finish. The `Termination`
+//! tag says how a game was won.
+
+use std::fmt::{Formatter,Error,Display};
+use std::str::FromStr;
+use std::fs::File;
+use std::io::{self,Read,Write};
+use std::path::Path;
+use std::time::{SystemTime,UNIX_EPOCH};
+
+use super::board::{Board,Cell,Colour,Variant};
+use super::clock::TimeControl;
+use super::command::{Move,GameResult,WinReason};
+
+///A day, for saying when a game was played
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Date {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    ///Today, in UTC
+    pub fn today() -> Date {
+        let days = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 86400;
+
+        //Converts days since 1970-01-01 to a date in the Gregorian calendar, counting years
+        //from March so that leap days come at the end
+        let z = days + 719468;
+        let era = z / 146097;
+        let day_of_era = z - era * 146097;
+        let year_of_era = (day_of_era - day_of_era/1460 + day_of_era/36524 - day_of_era/146096) / 365;
+        let day_of_year = day_of_era - (365*year_of_era + year_of_era/4 - year_of_era/100);
+        let month_from_march = (5*day_of_year + 2) / 153;
+        let day = day_of_year - (153*month_from_march + 2)/5 + 1;
+        let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Date { year: year as u32, month: month as u32, day: day as u32 }
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),Error> {
+        write!(f, "{:04}.{:02}.{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl FromStr for Date {
+    type Err = ();
+
+    ///Parses `%year.%month.%day`
+    fn from_str(s: &str) -> Result<Date, ()> {
+        let parts = s.split('.').map(|part| part.parse::<u32>()).collect::<Result<Vec<_>,_>>().map_err(|_| ())?;
+        match parts[..] {
+            [year, month, day] if month >= 1 && month <= 12 && day >= 1 && day <= 31 =>
+                Ok(Date { year: year, month: month, day: day }),
+            _ => Err(()),
+        }
+    }
+}
+
+///A game from start to finish, or as far as it got
+#[derive(Clone,Debug)]
+pub struct GameRecord {
+    pub black: String,
+    pub white: String,
+    ///When the game was played, if known
+    pub date: Option<Date>,
+    pub variant: Variant,
+    ///Where the game started from, usually the variant's layout
+    pub start: Board,
+    pub time_control: Option<TimeControl>,
+    pub moves: Vec<Move>,
+    ///How the game ended, or nothing if it didn't
+    pub result: Option<GameResult>,
+    ///Any other tags, in the order they were read
+    pub tags: Vec<(String,String)>,
+}
+
+impl GameRecord {
+    ///A record of a game which hasn't started, from the variant's layout
+    pub fn new(black: &str, white: &str, variant: Variant) -> GameRecord {
+        GameRecord {
+            black: black.to_string(),
+            white: white.to_string(),
+            date: None,
+            variant: variant,
+            start: Board::new_variant(variant),
+            time_control: None,
+            moves: Vec::new(),
+            result: None,
+            tags: Vec::new(),
+        }
+    }
+
+    ///The position before the first move and after each one
+    pub fn positions(&self) -> Vec<Board> {
+        let mut board = self.start.clone();
+        let mut positions = vec![board.clone()];
+        for mov in &self.moves {
+            //The moves were checked when the record was made or read
+            board.cells[mov.dx as usize][mov.dy as usize] = board.cells[mov.sx as usize][mov.sy as usize];
+            board.cells[mov.sx as usize][mov.sy as usize] = Cell::default();
+            positions.push(board.clone());
+        }
+        positions
+    }
+
+    ///Each move as it's written in the record, e.g. `1,0-1,2`
+    pub fn notation(&self) -> Vec<String> {
+        self.positions().iter().zip(self.moves.iter()).map(|(before, mov)| {
+            let captures = before.cells[mov.dx as usize][mov.dy as usize].has_piece;
+            format!("{},{}{}{},{}", mov.sx, mov.sy, if captures { 'x' } else { '-' }, mov.dx, mov.dy)
+        }).collect()
+    }
+
+    ///Reads a record from a file
+    pub fn load(path: &Path) -> Result<GameRecord,String> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        contents.parse::<GameRecord>().map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    ///Writes the record to a file
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        write!(file, "{}", self)
+    }
+}
+
+//The result as it's written at the end of the moves
+fn result_token(result: Option<GameResult>) -> &'static str {
+    match result {
+        Some(GameResult::Win(Colour::Black, _)) => "1-0",
+        Some(GameResult::Win(Colour::White, _)) => "0-1",
+        Some(GameResult::Draw) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+//Writes a tag on a line of its own, escaping anything in the value which would end it early
+fn write_tag(f: &mut Formatter, name: &str, value: &str) -> Result<(),Error> {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    writeln!(f, "[{} \"{}\"]", name, escaped)
+}
+
+//How many moves to a line, to keep lines short
+const MOVES_PER_LINE: usize = 8;
+
+impl Display for GameRecord {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),Error> {
+        write_tag(f, "Black", &self.black)?;
+        write_tag(f, "White", &self.white)?;
+        write_tag(f, "Date", &self.date.map_or("????.??.??".to_string(), |date| date.to_string()))?;
+        write_tag(f, "Variant", &self.variant.to_string())?;
+        if self.start.position() != Board::new_variant(self.variant).position() {
+            write_tag(f, "Position", &self.start.position())?;
+        }
+        if let Some(time_control) = self.time_control {
+            write_tag(f, "TimeControl", &time_control.to_string())?;
+        }
+        write_tag(f, "Result", result_token(self.result))?;
+        if let Some(GameResult::Win(_, reason)) = self.result {
+            write_tag(f, "Termination", &reason.to_string())?;
+        }
+        for &(ref name, ref value) in &self.tags {
+            write_tag(f, name, value)?;
+        }
+        writeln!(f)?;
+
+        for (i, mov) in self.notation().iter().enumerate() {
+            if i % 2 == 0 {
+                write!(f, "{}. ", i/2 + 1)?;
+            }
+            write!(f, "{}", mov)?;
+            write!(f, "{}", if (i + 1) % MOVES_PER_LINE == 0 { "\n" } else { " " })?;
+        }
+        writeln!(f, "{}", result_token(self.result))
+    }
+}
+
+//Reads a tag's name and value from a line like `[Name "value"]`
+fn parse_tag(line: &str) -> Option<(String,String)> {
+    if !line.starts_with('[') || !line.ends_with(']') {
+        return None;
+    }
+    let inner = &line[1..line.len()-1];
+    let space = inner.find(' ')?;
+    let (name, quoted) = (&inner[..space], inner[space..].trim());
+    if name.is_empty() || quoted.len() < 2 || !quoted.starts_with('"') || !quoted.ends_with('"') {
+        return None;
+    }
+
+    let mut value = String::new();
+    let mut chars = quoted[1..quoted.len()-1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.push(match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                c => c,
+            }),
+            '"' => return None,
+            c => value.push(c),
+        }
+    }
+    Some((name.to_string(), value))
+}
+
+//Reads a move written `SX,SY-DX,DY` or `SX,SYxDX,DY`
+fn parse_move(token: &str) -> Option<Move> {
+    let split = token.find(|c| c == '-' || c == 'x')?;
+    let cell = |s: &str| -> Option<(u8,u8)> {
+        let mut parts = s.splitn(2, ',');
+        Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+    };
+    let (sx, sy) = cell(&token[..split])?;
+    let (dx, dy) = cell(&token[split+1..])?;
+    Some(Move::new(sx, sy, dx, dy))
+}
+
+impl FromStr for GameRecord {
+    type Err = String;
+
+    ///Reads a record, checking that its moves are legal
+    fn from_str(s: &str) -> Result<GameRecord, String> {
+        let mut tags = Vec::new();
+        let mut lines = s.lines().enumerate().peekable();
+        while let Some(&(i, line)) = lines.peek() {
+            let line = line.trim();
+            if line.is_empty() {
+                lines.next();
+            } else if line.starts_with('[') {
+                tags.push(parse_tag(line).ok_or(format!("line {}: bad tag", i + 1))?);
+                lines.next();
+            } else {
+                break;
+            }
+        }
+
+        let tag = |name: &str| tags.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref value)| value.clone());
+        let variant = match tag("Variant") {
+            Some(name) => name.parse::<Variant>().map_err(|_| format!("unknown variant {}", name))?,
+            None => Variant::Standard,
+        };
+        let mut record = GameRecord::new(&tag("Black").unwrap_or_default(), &tag("White").unwrap_or_default(), variant);
+        record.date = tag("Date").and_then(|date| date.parse().ok());
+        if let Some(position) = tag("Position") {
+            record.start = position.parse::<Board>().map_err(|_| format!("bad position {}", position))?;
+        }
+        if let Some(time_control) = tag("TimeControl") {
+            record.time_control = Some(time_control.parse::<TimeControl>()
+                                       .map_err(|_| format!("bad time control {}", time_control))?);
+        }
+        let reason = match tag("Termination") {
+            Some(reason) => reason.parse::<WinReason>().map_err(|_| format!("unknown termination {}", reason))?,
+            None => WinReason::Connection,
+        };
+        let known = ["Black", "White", "Date", "Variant", "Position", "TimeControl", "Result", "Termination"];
+        record.tags = tags.iter().filter(|&&(ref name, _)| !known.contains(&name.as_str())).cloned().collect();
+
+        //The moves, with comments taken out
+        let mut text = String::new();
+        let mut comment = false;
+        for (_, line) in lines {
+            for c in line.chars() {
+                match c {
+                    '{' => comment = true,
+                    '}' => comment = false,
+                    c if !comment => text.push(c),
+                    _ => {},
+                }
+            }
+            text.push('\n');
+        }
+
+        let mut board = record.start.clone();
+        let mut turn = Colour::Black;
+        let mut result = None;
+        for token in text.split_whitespace() {
+            if result.is_some() {
+                return Err(format!("{} after the result", token));
+            }
+            //Move numbers
+            if token.ends_with('.') && token[..token.len()-1].parse::<u32>().is_ok() {
+                continue;
+            }
+
+            result = match token {
+                "1-0" => Some(Some(GameResult::Win(Colour::Black, reason))),
+                "0-1" => Some(Some(GameResult::Win(Colour::White, reason))),
+                "1/2-1/2" => Some(Some(GameResult::Draw)),
+                "*" => Some(None),
+                _ => None,
+            };
+            if result.is_some() {
+                continue;
+            }
+
+            let mov = parse_move(token).ok_or(format!("bad move {}", token))?;
+            if board.move_piece(&mov, &turn).is_err() {
+                return Err(format!("move {} ({}) is illegal", record.moves.len() + 1, token));
+            }
+            record.moves.push(mov);
+            turn = turn.other();
+        }
+
+        record.result = result.ok_or("the moves don't end with a result".to_string())?;
+        if let Some(result) = tag("Result") {
+            if result != result_token(record.result) {
+                return Err(format!("the Result tag says {} but the moves end with {}", result, result_token(record.result)));
+            }
+        }
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A few moves of the first legal kind from `start`
+    fn record_from(start: Board, variant: Variant) -> GameRecord {
+        let mut record = GameRecord::new("Alice \"the \\ Bold\"\nJones", "Bob\tSmith\r", variant);
+        record.start = start.clone();
+        let mut board = start;
+        let mut turn = Colour::Black;
+        for _ in 0..6 {
+            let mov = board.legal_moves(turn).remove(0);
+            assert!(board.move_piece(&mov, &turn).is_ok());
+            record.moves.push(mov);
+            turn = turn.other();
+        }
+        record
+    }
+
+    fn round_trip(record: &GameRecord) -> GameRecord {
+        record.to_string().parse::<GameRecord>().unwrap()
+    }
+
+    #[test]
+    fn round_trips_names_and_results() {
+        let results = [Some(GameResult::Win(Colour::Black, WinReason::Resignation)),
+                       Some(GameResult::Win(Colour::White, WinReason::Timeout)),
+                       Some(GameResult::Draw),
+                       None];
+        for &result in &results {
+            let mut record = record_from(Board::new(), Variant::Standard);
+            record.date = Some(Date { year: 2026, month: 10, day: 19 });
+            record.time_control = "300+5".parse().ok();
+            record.result = result;
+            record.tags.push(("Event".to_string(), "Club night".to_string()));
+
+            let read = round_trip(&record);
+            assert_eq!(read.black, record.black);
+            assert_eq!(read.white, record.white);
+            assert_eq!(read.date, record.date);
+            assert_eq!(read.time_control, record.time_control);
+            assert_eq!(read.moves, record.moves);
+            assert_eq!(read.result, record.result);
+            assert_eq!(read.tags, record.tags);
+        }
+    }
+
+    #[test]
+    fn round_trips_position() {
+        let record = record_from(Board::new_variant(Variant::ScrambledEggs), Variant::Standard);
+        assert!(record.to_string().contains("[Position "));
+
+        let read = round_trip(&record);
+        assert_eq!(read.variant, Variant::Standard);
+        assert_eq!(read.start.position(), record.start.position());
+        assert_eq!(read.moves, record.moves);
+    }
+
+    #[test]
+    fn skips_comments() {
+        let text = "[Black \"Alice\"]\n[White \"Bob\"]\n[Result \"*\"]\n\n\
+                    1. 1,0-1,2 {a quiet start,\n over two lines} 0,3-2,3 {no reply yet} *\n";
+        let record = text.parse::<GameRecord>().unwrap();
+        assert_eq!(record.moves, vec![Move::new(1,0,1,2), Move::new(0,3,2,3)]);
+        assert_eq!(record.result, None);
+    }
+
+    #[test]
+    fn rejects_illegal_moves_and_mismatched_results() {
+        assert!("[Result \"*\"]\n\n1. 0,3-2,3 *\n".parse::<GameRecord>().is_err());
+        assert!("[Result \"1-0\"]\n\n1. 1,0-1,2 0-1\n".parse::<GameRecord>().is_err());
+    }
+}
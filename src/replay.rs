@@ -0,0 +1,92 @@
+//! Stepping back and forth through recorded games
+
+use std::path::Path;
+
+use super::board::{Board,Colour};
+use super::command::Move;
+use super::record::GameRecord;
+
+///A recorded game and how far through it we are
+pub struct Replay {
+    pub record: GameRecord,
+    positions: Vec<Board>,
+    notation: Vec<String>,
+    //How many moves have been played in the position being looked at
+    current: usize,
+}
+
+impl Replay {
+    ///Starts at the beginning of the game
+    pub fn new(record: GameRecord) -> Replay {
+        Replay {
+            positions: record.positions(),
+            notation: record.notation(),
+            record: record,
+            current: 0,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Replay,String> {
+        GameRecord::load(path).map(Replay::new)
+    }
+
+    ///How many moves have been played in the position being looked at
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn move_count(&self) -> usize {
+        self.record.moves.len()
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.positions[self.current]
+    }
+
+    ///The move which led to the position being looked at
+    pub fn last_move(&self) -> Option<&Move> {
+        self.current.checked_sub(1).map(|i| &self.record.moves[i])
+    }
+
+    ///The side to move in the position being looked at. Black moves first.
+    pub fn turn(&self) -> Colour {
+        if self.current % 2 == 0 { Colour::Black } else { Colour::White }
+    }
+
+    ///Each move as it's written in the record
+    pub fn notation(&self) -> &[String] {
+        &self.notation
+    }
+
+    ///Looks at the position after `moves` moves, or at the end if there aren't that many
+    pub fn go_to(&mut self, moves: usize) {
+        self.current = moves.min(self.move_count());
+    }
+
+    pub fn forward(&mut self) {
+        let next = self.current + 1;
+        self.go_to(next);
+    }
+
+    pub fn back(&mut self) {
+        self.current = self.current.saturating_sub(1);
+    }
+
+    pub fn to_start(&mut self) {
+        self.current = 0;
+    }
+
+    pub fn to_end(&mut self) {
+        self.current = self.move_count();
+    }
+
+    ///What's happening in the position being looked at, e.g. whose move it is
+    pub fn status(&self) -> String {
+        match self.record.result {
+            Some(result) if self.current == self.move_count() => format!("Game over, {}", result),
+            None if self.current == self.move_count() => format!("Move {}, the game stopped here", self.current),
+            _ if self.current == 0 => format!("Start, {:?} to move", self.turn()),
+            _ => format!("Move {} of {}, {:?} to move", self.current, self.move_count(), self.turn()),
+        }
+    }
+}
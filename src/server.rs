@@ -1,8 +1,53 @@
 //! The networking part of the lines of action server.
+//! # Running the server
+//! `cargo run -- --help` lists the server's options. It listens on `DEFAULT_PORT` and
+//! `DEFAULT_WEBSOCKET_PORT` unless told otherwise, and `--port 0` picks any free port.
+//!
+//! ## The window
+//! `--human NAME` lets a person play one side, e.g. against `--ai greedy`, by clicking a
+//! piece and then where it should go. Y and N accept and decline a draw offer.
+//!
+//! Moves are passed on as soon as they're made. To watch a game at your own pace, press S
+//! (or start with `--playback step`) and space to let each move through. D holds each move
+//! for a second (see `--delay`) and A goes back to passing them straight on.
+//!
+//! The window numbers the columns and rows as the protocol does, and shows the last move,
+//! whose turn it is and each side's clock. Text is written in DejaVu Sans if it can be found,
+//! or another font given with `--font FILE`. The window can be resized, and its colours
+//! changed with `--theme FILE`, see [theme](../theme/index.html). Moves slide into place over
+//! a quarter of a second, or however long `--animation` says.
+//!
+//! The window needs CSFML. To build without it, e.g. just for the client API or a headless
+//! server, use `cargo build --no-default-features` or depend on the crate with
+//! `default-features = false`.
+//!
+//! ## Without a window
+//! `--headless` runs without a window, e.g. on a machine with no display. `--tui` shows the
+//! game in the terminal instead, e.g. over SSH, so logging is best sent elsewhere with
+//! `2>server.log`.
+//!
+//! ## Records
+//! Each game is recorded in the `games` directory once it's over, in the format described
+//! [here](../record/index.html). `--record FILE`, `--record-dir DIR` and `--no-record`
+//! change this. `--replay FILE` shows a recorded game, where the left and right arrow keys
+//! step through the moves and up and down jump to the start and end. With `--headless` it's
+//! shown in the terminal.
+//!
 //! # Protocol
 //! Optional parts are marked `[]`. Whitespace is important. Non-literals are marked `%`.
 //! `You wot m8?;` is returned for an unrecognised command.
 //!
+//! Every message ends with `;`, so messages can't otherwise contain one. Several messages
+//! can be sent at once, or one message in several parts. Whitespace between messages, such
+//! as the newline after each reply, is ignored. A message longer than `MAX_MESSAGE_LENGTH`
+//! bytes gets `Spit it out;` and is thrown away.
+//!
+//! A message which isn't valid UTF-8 gets `That's not even text;`. If the server itself
+//! goes wrong handling a command, it replies `Something went wrong: %reason;` and carries on.
+//!
+//! Once the game has ended, any request made by a player that would otherwise wait on or
+//! change the game returns `Game over, %result;`, see [Game results](#game-results).
+//!
 //! ## Register player:
 //! `Hello, my name is %name[, you killed my father, prepare to die];`
 //!
@@ -11,13 +56,29 @@
 //!
 //! `You've already registered, you asshat;` - this TCP connection has already registered
 //!
-//! `The game is full, get tae;` - the game already has two players registered
+//! `Oh, hai %name! Pull up a chair;` - the game already has two players registered, so
+//! you are a spectator. See [Spectating](#spectating).
 //!
 //! ## Move piece:
 //! `(%sx,%sy)[ ]->[ ](%dx,%dy);`
 //!
+//! Black moves first, then the players take turns.
+//!
 //! ### Returns:
-//! Nothing, yet
+//! `Move successful;` - success
+//!
+//! `You can't move air, ya numpty;` - there is no piece at (%sx,%sy)
+//!
+//! `Stick to your own pieces!;` - the piece at (%sx,%sy) belongs to your opponent
+//!
+//! `That's no' how the pieces move;` - the move is off the board or against the rules, see
+//! [Get legal moves](#get-legal-moves)
+//!
+//! `Wait your turn;` - it's your opponent's move
+//!
+//! `Hold your horses, the game hasn't started;` - the game hasn't started
+//!
+//! `Game over, %result;` - your move won the game, or the game had already ended
 //!
 //! ## Send message to be displayed by the server:
 //! `"%message";`
@@ -30,183 +91,771 @@
 //!
 //! ### Returns:
 //! `You are %colour and %opponent wants to batter you;` - game has started
+//!
 //! `Who even are you?;` - you have not yet registered
 //!
 //! ## Get opponent's move:
-//! `Gimmeh;`
+//! `Gimmeh!;`
+//!
+//! ### Returns:
+//! `(%sx,%sy)->(%dx,%dy);` - your opponent's move
+//!
+//! `They fancy a draw;` - your opponent has offered a draw, which you should accept or decline
+//!
+//! `They don't fancy a draw;` - your opponent declined your offer of a draw
+//!
+//! `Game over, %result;` - the game has ended, see [Game results](#game-results)
+//!
+//! ## Resign:
+//! `I give up;`
+//!
+//! ### Returns:
+//! `Game over, %result;` - your opponent wins, or the game had already ended
+//!
+//! `Hold your horses, the game hasn't started;` - the game hasn't started
+//!
+//! `Who even are you?;` - you have not yet registered
+//!
+//! ## Offer a draw:
+//! `Fancy a draw?;`
+//!
+//! The offer stands until it's answered or either side makes a move.
+//!
+//! ### Returns:
+//! `I'll ask them;` - your opponent will be told the next time they ask for a move
+//!
+//! `Game over, it's a draw;` - your opponent had already offered you a draw
+//!
+//! Along with the resignation errors
+//!
+//! ## Accept a draw:
+//! `Aye, go on then;`
 //!
 //! ### Returns:
-//! Nothing, yet
+//! `Game over, it's a draw;` - success
+//!
+//! `Nobody offered you a draw;` - your opponent hasn't offered a draw, or a move has been made
+//! since
+//!
+//! Along with the resignation errors
+//!
+//! ## Decline a draw:
+//! `Not a chance;`
+//!
+//! ### Returns:
+//! `Suit yourself;` - your opponent will be told the next time they ask for a move
+//!
+//! `Nobody offered you a draw;` - your opponent hasn't offered a draw
+//!
+//! Along with the resignation errors
+//!
+//! ## Get the state of the game:
+//! `What's the state of play?;`
+//!
+//! This can be sent by anyone, including spectators and connections which haven't registered.
+//!
+//! ### Returns:
+//! `The state of play: %position, %colour to move, move %number, %status;` - `%position` is
+//! as described in [Spectating](#spectating), `%number` starts at 1 and counts both sides'
+//! moves, and `%status` is one of `waiting for players`, `in progress` or `game over, %result`.
+//!
+//! ## Get legal moves:
+//! `Where can I go?;` - moves for the side to move
+//!
+//! `Where can (%x,%y) go?;` - moves for the piece at (%x,%y)
+//!
+//! A piece moves in a straight line, horizontally, vertically or diagonally, exactly as many
+//! cells as there are pieces (of either colour) on that line. It may jump over its own
+//! pieces but not its opponent's, and captures an opponent's piece by landing on it. Black
+//! moves first. Like getting the state of the game, this can be sent by anyone.
+//!
+//! ### Returns:
+//! `Your options are: %moves;` - `%moves` is a list of moves in the form
+//! `(%sx,%sy)->(%dx,%dy)` separated by `, `. It is empty if there are no moves or the game
+//! has ended.
+//!
+//! ## Say hello:
+//! `Wotcha, I speak version %version;`
+//!
+//! Optional, and can be sent at any time by anyone. `%version` is the newest version of the
+//! protocol the client speaks, starting at 1. The server speaks `PROTOCOL_VERSION`; clients
+//! which don't say hello get the current version. New commands, replies and capabilities
+//! only ever come with a new version, so clients should ignore capabilities they don't know.
+//!
+//! ### Returns:
+//! `Aye, we'll speak version %version and I can do %capabilities;` - `%version` is the newest
+//! version both sides speak. `%capabilities` is a list separated by `, ` of:
+//!
+//! * `json` - the [JSON protocol](#json-protocol) can be used
+//! * `variant:%name` - games can be played with the named rules, e.g. `variant:standard`. There's
+//!   one for each variant, whichever this game is using.
+//! * `clocks` - the game has a time control
+//!
+//! ## Game results
+//! `%result` is either `it's a draw` or `%colour wins by %reason`, where `%reason` is one of:
+//!
+//! * `connection` - the winner connected all of their pieces into one group
+//! * `resignation` - the loser resigned
+//! * `disconnection` - the loser's connection closed
+//! * `timeout` - the loser ran out of time
+//!
+//! ## Spectating
+//! Connections which register once the game is full become spectators. Straight after
+//! registering they are sent the initial position followed by every move played so far,
+//! then each move as it is played. Spectators don't need to send anything to receive these.
+//!
+//! `Here's the board: %position;` - the initial position. Rows are separated by `/` and
+//! listed from y=0 to y=7, each giving cells from x=0 to x=7 as `w`, `b` or `.` for empty.
+//!
+//! `(%sx,%sy)->(%dx,%dy);` - a move, played by each side in turn starting with Black
+//!
+//! `Game over, %result;` - the game has ended
+//!
+//! # JSON protocol
+//! If the first thing a connection sends starts with `{`, it speaks JSON instead of the text
+//! protocol above for the rest of the connection. Every message is a single-line JSON object
+//! ending in a newline, with a `type` field saying what it is, or an `event` field for
+//! [events](#events). The commands and their behaviour are exactly the same as for the
+//! text protocol. `MAX_MESSAGE_LENGTH` applies.
+//!
+//! A cell is an array `[%x, %y]` and a colour is `"White"` or `"Black"`. Moves have a
+//! `from` and a `to` cell.
+//!
+//! ## Requests
+//! | `type` | Other fields | Text equivalent |
+//! |--------|--------------|-----------------|
+//! | `register` | `name` - letters and numbers only | `Hello, my name is %name;` |
+//! | `ready` | | `Bring it, yo;` |
+//! | `move` | `from`, `to` | `(%sx,%sy)->(%dx,%dy);` |
+//! | `get_move` | | `Gimmeh!;` |
+//! | `message` | `text` | `"%message";` |
+//! | `resign` | | `I give up;` |
+//! | `offer_draw` | | `Fancy a draw?;` |
+//! | `accept_draw` | | `Aye, go on then;` |
+//! | `decline_draw` | | `Not a chance;` |
+//! | `get_board` | | `What's the state of play?;` |
+//! | `legal_moves` | optionally `from` | `Where can I go?;` or `Where can (%x,%y) go?;` |
+//! | `hello` | `version` | `Wotcha, I speak version %version;` |
+//!
+//! ## Responses
+//! Every request gets exactly one response, including `message`.
+//!
+//! | `type` | Other fields | Sent for |
+//! |--------|--------------|----------|
+//! | `ok` | | success with nothing more to say |
+//! | `registered` | `name`, `spectator` - whether you are spectating | `register` |
+//! | `start` | `colour`, `opponent` | `ready` |
+//! | `move` | `from`, `to` | `get_move` |
+//! | `draw_offered` | | `get_move` |
+//! | `draw_declined` | | `get_move` |
+//! | `game_over` | `winner` - a colour, or `null` for a draw, and `reason` for a win | see [Game results](#game-results) |
+//! | `board` | `position`, `turn`, `move_number`, `status` - `waiting`, `in_progress` or `game_over`, with the `game_over` fields for the last | `get_board` |
+//! | `moves` | `moves` - an array of moves | `legal_moves` |
+//! | `hello` | `version`, `capabilities` - an array of strings | `hello` |
+//! | `error` | `error` - a code from the list below, `message` - the text protocol's message | failure |
+//!
+//! Error codes are `already_registered`, `not_registered`, `game_full`, `no_piece`,
+//! `wrong_piece`, `illegal_move`, `not_your_turn`, `not_started`, `no_draw_offered` and
+//! `other`, matching the text protocol's errors, plus `unrecognised`, `too_long` and
+//! `not_text` for messages which couldn't be understood.
+//!
+//! ## Events
+//! Events have an `event` field instead of `type`, so they can't be confused with responses.
+//!
+//! | `event` | Other fields | Text equivalent |
+//! |---------|--------------|-----------------|
+//! | `position` | `position` | `Here's the board: %position;` |
+//! | `move` | `from`, `to` | `(%sx,%sy)->(%dx,%dy);` |
+//! | `game_over` | the same as the `game_over` response | `Game over, %result;` |
+//!
+//! # WebSockets
+//! Browsers can connect with a WebSocket on `DEFAULT_WEBSOCKET_PORT` instead of raw TCP on
+//! `DEFAULT_PORT`, unless the server was told to use other ports.
+//! Either protocol can be spoken, chosen by the first message as usual. Each WebSocket
+//! message carries one or more messages, so text messages still end with `;`, but a JSON
+//! message needn't end with a newline. Every reply and event is sent as its own text message.
 
 extern crate regex;
+extern crate tungstenite;
 
-use super::command::{Move,Command,CommandProducer,GetCommandErr,CommandResponse,CommandData,CommandErr,CommandOk};
-use std::net::{TcpListener, TcpStream, SocketAddr};
+use super::command::{Move,Command,CommandProducer,GetCommandErr,CommandResponse,CommandData,CommandErr,CommandOk,Event,GameResult,GameStatus};
+use super::json;
+use std::net::{TcpListener, TcpStream, SocketAddr, Shutdown};
 use std::thread;
-use std::str;
-use self::regex::{Regex,Captures};
-use std::io::{Read,Write};
-use std::sync::mpsc::{channel,Sender,Receiver,TryRecvError};
+use self::regex::Regex;
+use self::tungstenite::{WebSocket,Message};
+use self::tungstenite::protocol::Role;
+use self::tungstenite::Error as WsError;
+use std::io::{self,Read,Write};
+use std::sync::mpsc::{channel,Sender,Receiver,TryRecvError,RecvTimeoutError};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicIsize,Ordering};
+use std::time::Duration;
+use std::cmp;
 
-pub const PORT: u16 = 1234;
+///The port the server listens on unless told otherwise
+pub const DEFAULT_PORT: u16 = 1234;
+///The port the server accepts WebSockets on unless told otherwise
+pub const DEFAULT_WEBSOCKET_PORT: u16 = 1235;
+pub const MAX_MESSAGE_LENGTH: usize = 1024;
 
-fn handle_message(buf: &[u8], command_sender: &Sender<Command>,
-                  response_send: &Sender<CommandResponse>,
-                  response_recv: &Receiver<CommandResponse>,
-                  stream: &mut TcpStream, id: i32) {
+//The ways a connection can talk to the server, decided by its first message
+#[derive(Clone,Copy,Debug,PartialEq)]
+enum Protocol {
+    Text,
+    Json,
+}
+
+impl Protocol {
+    //JSON messages start with an object, anything else is text
+    fn detect(first_byte: u8) -> Protocol {
+        if first_byte == b'{' { Protocol::Json } else { Protocol::Text }
+    }
+
+    fn delimiter(&self) -> u8 {
+        match *self {
+            Protocol::Text => b';',
+            Protocol::Json => b'\n',
+        }
+    }
+
+    fn parse(&self, msg: &str, id: i32) -> Result<CommandData,CommandErr> {
+        match *self {
+            Protocol::Text => parse_text(msg, id),
+            Protocol::Json => json::parse_request(msg, id),
+        }.ok_or(CommandErr::Unrecognised)
+    }
+
+    //How to reply to `request`, if at all
+    fn format_response(&self, request: &CommandData, response: &CommandResponse) -> Option<String> {
+        match *self {
+            Protocol::Text => format_text_response(request, response),
+            Protocol::Json => Some(json::format_response(request, response)),
+        }
+    }
+
+    fn format_event(&self, event: &Event) -> String {
+        match *self {
+            Protocol::Text => format_text_event(event),
+            Protocol::Json => json::format_event(event),
+        }
+    }
+
+    //Why a message couldn't be understood
+    fn format_bad_message(&self, why: &CommandErr) -> String {
+        match *self {
+            Protocol::Text => format!("{};\n", why),
+            Protocol::Json => json::format_command_err(why),
+        }
+    }
+}
+
+//Splits the bytes read from a connection into messages
+struct MessageBuffer {
+    buf: Vec<u8>,
+    delimiter: u8,
+    //Set after a message got too long, until the end of it has been thrown away
+    discarding: bool,
+}
+
+impl MessageBuffer {
+    fn new(delimiter: u8) -> MessageBuffer {
+        MessageBuffer { buf: Vec::new(), delimiter: delimiter, discarding: false }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    //The first byte of the next message, if any has arrived
+    fn first_byte(&self) -> Option<u8> {
+        self.buf.iter().cloned().find(|c| !c.is_ascii_whitespace())
+    }
+
+    //Returns the next complete message, with leading whitespace removed, or `Err` when a
+    //message is too long
+    fn next_message(&mut self) -> Option<Result<Vec<u8>,()>> {
+        loop {
+            let delimiter = self.delimiter;
+
+            if self.discarding {
+                match self.buf.iter().position(|&c| c == delimiter) {
+                    Some(end) => { self.buf.drain(..end+1); self.discarding = false; },
+                    None => { self.buf.clear(); return None; },
+                }
+                continue;
+            }
+
+            //The delimiter may itself be whitespace, so look for it after the whitespace
+            let start = self.buf.iter()
+                .position(|c| !c.is_ascii_whitespace())
+                .unwrap_or(self.buf.len());
+            self.buf.drain(..start);
+
+            return match self.buf.iter().position(|&c| c == delimiter) {
+                Some(end) if end < MAX_MESSAGE_LENGTH =>
+                    Some(Ok(self.buf.drain(..end+1).collect())),
+                None if self.buf.len() <= MAX_MESSAGE_LENGTH => None,
+                _ => { self.discarding = true; Some(Err(())) },
+            };
+        }
+    }
+}
+
+fn parse_text(msg: &str, id: i32) -> Option<CommandData> {
     let move_re = Regex::new(r"^\((\d),(\d)\) ?-> ?\((\d),(\d)\);").unwrap();
     let register_re = Regex::new(r"^Hello, my name is ([A-Za-z0-9]+)(, you killed my father, prepare to die)?;").unwrap();
     let message_re = Regex::new("^\"(.+)\";").unwrap();
     let get_re = Regex::new("^Gimmeh!;").unwrap();
     let ready_re = Regex::new("^Bring it, yo;").unwrap();
-
-    let buf = str::from_utf8(buf).unwrap();
+    let resign_re = Regex::new("^I give up;").unwrap();
+    let offer_draw_re = Regex::new(r"^Fancy a draw\?;").unwrap();
+    let accept_draw_re = Regex::new("^Aye, go on then;").unwrap();
+    let decline_draw_re = Regex::new("^Not a chance;").unwrap();
+    let board_re = Regex::new(r"^What's the state of play\?;").unwrap();
+    let legal_moves_re = Regex::new(r"^Where can (I|\((\d),(\d)\)) go\?;").unwrap();
+    let hello_re = Regex::new(r"^Wotcha, I speak version ([1-9]\d{0,8});").unwrap();
 
     //This is a move
-    if move_re.is_match(&buf) {
+    if let Some(caps) = move_re.captures(msg) {
         //Transform captures into vector of u8s
-        let caps = move_re.captures(&buf).unwrap()
-            .iter()
+        let caps = caps.iter()
             .skip(1)
             .map(|x:Option<&str>| { x.unwrap().parse::<u8>().unwrap() })
             .collect::<Vec<_>>();
 
-        let mov = CommandData::Move(id,Move::new_vec(caps));
-        let command = Command{ data: mov,
-                              reply: response_send.clone()};
-
-        command_sender.send(command).unwrap();
-        
-        match response_recv.recv().unwrap() {
-            Ok(CommandOk::None) =>
-            { stream.write(b"Move successful;\n"); },
-            Err(CommandErr::NoPiece) =>
-            { stream.write(b"You can't move air, ya numpty;\n"); },
-            Err(CommandErr::WrongPiece) =>
-            { stream.write(b"Stick to your own pieces!;\n"); },
-            _ => {},
-        }
+        Some(CommandData::Move(id,Move::new_vec(caps)))
     }
 
     //This is a registration
-    else if register_re.is_match(&buf) {
-        let name = register_re.captures(&buf).unwrap().at(1).unwrap();
-        let reg = CommandData::Register(name.to_string(),id);
-        let command = Command{ data: reg,
-                              reply: response_send.clone()};
-
-        command_sender.send(command).unwrap();
-        match response_recv.recv().unwrap() {
-            Ok(CommandOk::None) =>
-            { stream.write(format!("Oh, hai {}!;\n",name).as_bytes()); },
-            Err(CommandErr::AlreadyRegistered) =>
-            { stream.write(b"You've already registered, you asshat;\n"); },
-            Err(CommandErr::GameFull) =>
-            { stream.write(b"The game is full, get tae;\n"); },
-            Err(CommandErr::Other(s)) => panic!(s),
-            _ => panic!(),
-        };
+    else if let Some(caps) = register_re.captures(msg) {
+        Some(CommandData::Register(caps.at(1).unwrap().to_string(),id))
     }
 
     //This is a message
-    else if message_re.is_match(&buf) {
-        let msg = CommandData::Message(
-            id,
-            message_re.captures(&buf).unwrap().at(1).unwrap().to_string()
-            );
-        let command = Command{ data: msg,
-                              reply: response_send.clone()};
-
-        command_sender.send(command).unwrap();
-        response_recv.recv().unwrap().unwrap();
+    else if let Some(caps) = message_re.captures(msg) {
+        Some(CommandData::Message(id, caps.at(1).unwrap().to_string()))
     }
 
     //This is a request for a move
-    else if get_re.is_match(&buf) {
-        let command = Command{ data: CommandData::GetMove(id),
-                              reply: response_send.clone()};
-        command_sender.send(command).unwrap();
-        
-        match response_recv.recv().unwrap() {
-            Ok(CommandOk::Move(mov)) =>
-            { stream.write(format!("({},{})->({},{});\n", mov.sx,mov.sy,mov.dx,mov.dy).as_bytes()).unwrap(); },
-            _ => panic!("Error in getting move"),
-        }
+    else if get_re.is_match(msg) {
+        Some(CommandData::GetMove(id))
+    }
+
+    //User wants the game to end
+    else if resign_re.is_match(msg) {
+        Some(CommandData::Resign(id))
+    }
+    else if offer_draw_re.is_match(msg) {
+        Some(CommandData::OfferDraw(id))
+    }
+    else if accept_draw_re.is_match(msg) {
+        Some(CommandData::AcceptDraw(id))
+    }
+    else if decline_draw_re.is_match(msg) {
+        Some(CommandData::DeclineDraw(id))
     }
 
     //User is ready to start
-    else if ready_re.is_match(&buf) {
-        let command = Command{ data: CommandData::Ready(id),
-                              reply: response_send.clone()};
-        command_sender.send(command).unwrap();
-        
-        match response_recv.recv().unwrap() {
-            Ok(CommandOk::None) => {},
-            Ok(CommandOk::Ready(opponent,col)) =>
-            { stream.write(format!("You are {:?} and {} wants to batter you;\n",col,opponent).as_bytes()); },
-            Err(CommandErr::NotRegistered) =>
-            { stream.write(b"Who even are you?;\n"); },
-            _ => panic!(),
-        }
+    else if ready_re.is_match(msg) {
+        Some(CommandData::Ready(id))
+    }
+
+    //User wants to know what the board looks like
+    else if board_re.is_match(msg) {
+        Some(CommandData::GetBoard(id))
     }
 
+    //User wants to know where they can move
+    else if let Some(caps) = legal_moves_re.captures(msg) {
+        let cell = match (caps.at(2), caps.at(3)) {
+            (Some(x), Some(y)) => Some((x.parse::<u8>().unwrap(), y.parse::<u8>().unwrap())),
+            _ => None,
+        };
+        Some(CommandData::GetLegalMoves(id, cell))
+    }
+
+    //User wants to know what we can do
+    else if let Some(caps) = hello_re.captures(msg) {
+        Some(CommandData::Hello(id, caps.at(1).unwrap().parse::<u32>().unwrap()))
+    }
 
     //User is drunk
     else {
-        stream.write(b"You wot m8?;\n");
+        None
+    }
+}
+
+fn format_move(mov: &Move) -> String {
+    format!("({},{})->({},{})", mov.sx,mov.sy,mov.dx,mov.dy)
+}
+
+fn format_text_response(request: &CommandData, response: &CommandResponse) -> Option<String> {
+    let msg = match (request, response) {
+        (_, &Ok(CommandOk::GameOver(result))) => game_over_message(result),
+        (_, &Err(ref err)) => format!("{}", err),
+
+        (&CommandData::Move(..), &Ok(CommandOk::None)) => "Move successful".to_string(),
+        (&CommandData::Register(ref name,_), &Ok(CommandOk::None)) =>
+            format!("Oh, hai {}!", name),
+        (&CommandData::Register(ref name,_), &Ok(CommandOk::Spectating(_))) =>
+            format!("Oh, hai {}! Pull up a chair", name),
+        (&CommandData::Message(..), &Ok(CommandOk::None)) => return None,
+        (&CommandData::OfferDraw(_), &Ok(CommandOk::None)) => "I'll ask them".to_string(),
+        (&CommandData::DeclineDraw(_), &Ok(CommandOk::None)) => "Suit yourself".to_string(),
+
+        (_, &Ok(CommandOk::Ready(ref opponent, colour))) =>
+            format!("You are {:?} and {} wants to batter you", colour, opponent),
+        (_, &Ok(CommandOk::Move(ref mov))) => format_move(mov),
+        (_, &Ok(CommandOk::DrawOffered)) => "They fancy a draw".to_string(),
+        (_, &Ok(CommandOk::DrawDeclined)) => "They don't fancy a draw".to_string(),
+        (_, &Ok(CommandOk::Board(ref state))) => {
+            let status = match state.status {
+                GameStatus::Waiting => "waiting for players".to_string(),
+                GameStatus::InProgress => "in progress".to_string(),
+                GameStatus::Over(result) => format!("game over, {}", result),
+            };
+            format!("The state of play: {}, {:?} to move, move {}, {}",
+                    state.board.position(), state.turn, state.move_number, status)
+        },
+        (_, &Ok(CommandOk::Moves(ref moves))) =>
+            format!("Your options are: {}",
+                    moves.iter().map(format_move).collect::<Vec<_>>().join(", ")),
+        (_, &Ok(CommandOk::Hello(ref handshake))) =>
+            format!("Aye, we'll speak version {} and I can do {}", handshake.version,
+                    handshake.capabilities.iter()
+                        .map(|c| c.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")),
+
+        //Anything else is a bug in the engine rather than the client's fault
+        (_, &Ok(ref ok)) => {
+            error!("Unexpected response from engine: {:?}", ok);
+            format!("{}", CommandErr::Other("unexpected response".to_string()))
+        },
+    };
+    Some(format!("{};\n", msg))
+}
+
+fn game_over_message(result: GameResult) -> String {
+    format!("Game over, {}", result)
+}
+
+fn format_text_event(event: &Event) -> String {
+    match *event {
+        Event::Position(ref board) => format!("Here's the board: {};\n", board.position()),
+        Event::Move(ref mov) => format!("{};\n", format_move(mov)),
+        Event::GameOver(result) => format!("{};\n", game_over_message(result)),
     }
 }
 
-fn handle_connection(mut stream: TcpStream, command_sender: Sender<Command>, id: i32) {
+//Forwards game events to a spectator until either end goes away
+fn send_events<M: From<String>>(outbox: Sender<M>, events: Receiver<Event>, protocol: Protocol) {
+    for event in events.iter() {
+        if outbox.send(protocol.format_event(&event).into()).is_err() {
+            break;
+        }
+    }
+}
+
+//Writes everything sent to a TCP connection's outbox
+fn write_outbox(mut stream: TcpStream, outbox: Receiver<String>) {
+    for msg in outbox.iter() {
+        if stream.write_all(msg.as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+fn handle_message<M: From<String> + Send + 'static>(request: CommandData, protocol: Protocol,
+                  command_sender: &Sender<Command>,
+                  response_send: &Sender<CommandResponse>,
+                  response_recv: &Receiver<CommandResponse>,
+                  outbox: &Sender<M>, id: i32) {
+    let command = Command{ data: request.clone(),
+                          reply: response_send.clone()};
+    command_sender.send(command).unwrap();
+    let response = response_recv.recv().unwrap();
+
+    //The outbox only closes when the connection has failed, which the reader will notice
+    if let Some(reply) = protocol.format_response(&request, &response) {
+        let _ = outbox.send(reply.into());
+    }
+
+    //Spectators get sent events as they happen, alongside any replies
+    if let Ok(CommandOk::Spectating(events)) = response {
+        debug!("Sending events to spectator {}", id);
+        let outbox = outbox.clone();
+        thread::spawn(move|| { send_events(outbox, events, protocol); });
+    }
+}
+
+//Speaks the protocol to a client, whatever it's connected over. Replies are sent to `outbox`.
+fn handle_connection<R: Read, M: From<String> + Send + 'static>(mut input: R, outbox: Sender<M>,
+                              command_sender: Sender<Command>, id: i32) {
     debug!("Accepted");
     let mut buf = [0 as u8;256];
+    let mut messages = MessageBuffer::new(b';');
+    let mut protocol = None;
 
     //Used to get errors back from the engine
     let (response_send,response_recv) = channel();
 
     loop {
-        match stream.read(&mut buf) {
+        match input.read(&mut buf) {
             Ok(0) => break,
-            Ok(_) => handle_message(&buf, &command_sender,
-                                    &response_send, &response_recv,
-                                    &mut stream, id),
-            Err(e) => panic!(e),
+            Ok(n) => messages.push(&buf[..n]),
+            Err(e) => { debug!("Read failed: {}", e); break; },
+        }
+
+        if protocol.is_none() {
+            protocol = messages.first_byte().map(Protocol::detect);
+            if let Some(protocol) = protocol {
+                debug!("Connection {} speaks {:?}", id, protocol);
+                messages.delimiter = protocol.delimiter();
+            }
+        }
+        let protocol = match protocol {
+            Some(protocol) => protocol,
+            None => continue,
+        };
+
+        while let Some(message) = messages.next_message() {
+            let request = message.map_err(|()| CommandErr::TooLong)
+                .and_then(|message| String::from_utf8(message).map_err(|_| CommandErr::NotText))
+                .and_then(|message| protocol.parse(&message, id));
+
+            match request {
+                Ok(request) => handle_message(request, protocol, &command_sender,
+                                              &response_send, &response_recv,
+                                              &outbox, id),
+                Err(why) => { let _ = outbox.send(protocol.format_bad_message(&why).into()); },
+            }
+        }
+    }
+
+    debug!("Connection {} closed", id);
+    command_sender.send(Command{ data: CommandData::Leave(id),
+                                 reply: response_send.clone()}).unwrap();
+}
+
+fn handle_tcp(stream: TcpStream, command_sender: Sender<Command>, id: i32) {
+    let (outbox_send, outbox_recv) = channel();
+    match stream.try_clone() {
+        Ok(writer) => { thread::spawn(move|| { write_outbox(writer, outbox_recv); }); },
+        Err(e) => { error!("Couldn't set up connection {}: {}", id, e); return; },
+    }
+    handle_connection(stream, outbox_send, command_sender, id);
+}
+
+//The messages received over a WebSocket, read as a stream of bytes
+struct WebSocketInput {
+    messages: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl Read for WebSocketInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.messages.recv() {
+                Ok(message) => self.pending = message,
+                //The WebSocket has closed
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let n = cmp::min(buf.len(), self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+//The reading end of a WebSocket. Once the handshake is done only the writer sends anything,
+//so whatever the reader's side of the protocol would send (answers to pings, closes) goes
+//nowhere, and the reader passes the frames it's answering on to the writer instead.
+struct WebSocketReader {
+    stream: TcpStream,
+    handshaking: bool,
+}
+
+impl Read for WebSocketReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for WebSocketReader {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.handshaking {
+            self.stream.write(buf)
+        } else {
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+//Passes messages from a WebSocket to the protocol handler until either end goes away
+fn read_websocket(mut socket: WebSocket<WebSocketReader>, input: Sender<Vec<u8>>,
+                  outbox: Sender<Message>, id: i32) {
+    loop {
+        let mut message = match socket.read() {
+            Ok(Message::Text(text)) => text.into_bytes(),
+            Ok(Message::Binary(bytes)) => bytes,
+            Ok(Message::Ping(data)) => { let _ = outbox.send(Message::Pong(data)); continue; },
+            //The writer answers the close, and stops once it has
+            Ok(close@Message::Close(_)) => { let _ = outbox.send(close); continue; },
+            Ok(_) => continue,
+            Err(WsError::ConnectionClosed) => break,
+            Err(e) => { debug!("WebSocket {} read failed: {}", id, e); break; },
+        };
+
+        //Each WebSocket message ends a JSON message, so browsers needn't add the newline
+        if message.last() != Some(&b'\n') {
+            message.push(b'\n');
+        }
+        if input.send(message).is_err() {
+            break;
+        }
+    }
+}
+
+//Sends everything sent to a WebSocket connection's outbox, closing the connection when the
+//client asks to or the outbox closes
+fn write_websocket(mut socket: WebSocket<TcpStream>, outbox: Receiver<Message>, id: i32) {
+    for msg in outbox.iter() {
+        let result = match msg {
+            Message::Close(frame) => socket.close(frame).and_then(|()| socket.flush()),
+            msg => socket.send(msg),
+        };
+        match result {
+            Ok(()) => (),
+            Err(WsError::ConnectionClosed) => break,
+            Err(e) => { debug!("WebSocket {} write failed: {}", id, e); break; },
+        }
+        if !socket.can_write() {
+            break;
+        }
+    }
+
+    if socket.can_write() {
+        let _ = socket.close(None).and_then(|()| socket.flush());
+    }
+    //Stops the reader too, if the client hasn't gone away already
+    let _ = socket.get_ref().shutdown(Shutdown::Both);
+}
+
+fn handle_websocket(stream: TcpStream, command_sender: Sender<Command>, id: i32) {
+    let mut reader = match tungstenite::accept(WebSocketReader{ stream: stream, handshaking: true }) {
+        Ok(socket) => socket,
+        Err(e) => { debug!("WebSocket handshake failed for {}: {}", id, e); return; },
+    };
+    reader.get_mut().handshaking = false;
+    //Reads and writes are done on separate threads, each with its own end of the socket. Only
+    //the reading end has seen the handshake, so it keeps anything the client sent after it.
+    let writer = match reader.get_ref().stream.try_clone() {
+        Ok(stream) => WebSocket::from_raw_socket(stream, Role::Server, None),
+        Err(e) => { error!("Couldn't set up WebSocket {}: {}", id, e); return; },
+    };
+
+    let (input_send, input_recv) = channel();
+    let (outbox_send, outbox_recv) = channel();
+    let control_send = outbox_send.clone();
+    thread::spawn(move|| { read_websocket(reader, input_send, control_send, id); });
+    thread::spawn(move|| { write_websocket(writer, outbox_recv, id); });
+
+    let input = WebSocketInput { messages: input_recv, pending: Vec::new() };
+    handle_connection(input, outbox_send, command_sender, id);
+}
+
+//Hands each connection to a listener to `handle` on its own thread
+fn listen(listener: TcpListener, command_sender: Sender<Command>, next_id: Arc<AtomicIsize>,
+          handle: fn(TcpStream, Sender<Command>, i32)) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let send = command_sender.clone();
+                let id = next_id.fetch_add(1, Ordering::SeqCst) as i32;
+                thread::spawn(move|| {
+                    handle(stream, send, id);
+                });
+            }
+            Err(e) => { error!("Couldn't accept connection: {}", e); }
         }
     }
 }
 
 pub struct GameServer {
     command_receiver: Receiver<Command>,
-    handle: thread::JoinHandle<()>,
+    command_sender: Sender<Command>,
+    addr: SocketAddr,
+    websocket_addr: Option<SocketAddr>,
+    next_id: Arc<AtomicIsize>,
 }
 
 impl GameServer {
-    pub fn new() -> GameServer {
+    ///Listens for raw TCP connections on `addr`, and WebSocket connections on `websocket_addr`
+    ///if given. A port of 0 picks any free port, see `addr` and `websocket_addr` for which.
+    pub fn new(addr: SocketAddr, websocket_addr: Option<SocketAddr>) -> io::Result<GameServer> {
         let (send,recv) = channel();
+        //Connection ids are shared between the listeners so they never clash
+        let next_id = Arc::new(AtomicIsize::new(1));
 
-        let handle = thread::spawn(move || {
-            let mut id = 0;
-            let listener = TcpListener::bind(("127.0.0.1",PORT))
-                .ok()
-                .expect(&format!("Could not bind to port {}", PORT));
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(stream) => {
-                        let send = send.clone();
-                        id += 1;
-                        thread::spawn(move|| {
-                            handle_connection(stream, send, id);
-                        });
-                    }
-                    Err(e) => { panic!(e); }
-                }
-            }
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let websocket_listener = match websocket_addr {
+            Some(websocket_addr) => Some(TcpListener::bind(websocket_addr)?),
+            None => None,
+        };
+        let websocket_addr = match websocket_listener {
+            Some(ref listener) => Some(listener.local_addr()?),
+            None => None,
+        };
+
+        let command_sender = send.clone();
+        //The listeners run until the program exits
+        let (tcp_send, tcp_id) = (send.clone(), next_id.clone());
+        thread::spawn(move || {
+            listen(listener, tcp_send, tcp_id, handle_tcp);
         });
+        if let Some(listener) = websocket_listener {
+            let websocket_id = next_id.clone();
+            thread::spawn(move || {
+                listen(listener, send, websocket_id, handle_websocket);
+            });
+        }
 
-        GameServer {
+        Ok(GameServer {
             command_receiver: recv,
-            handle: handle,
-        }
+            command_sender: command_sender,
+            addr: addr,
+            websocket_addr: websocket_addr,
+            next_id: next_id,
+        })
+    }
+
+    ///The address raw TCP connections are accepted on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    ///The address WebSocket connections are accepted on, if they are
+    pub fn websocket_addr(&self) -> Option<SocketAddr> {
+        self.websocket_addr
+    }
+
+    ///Somewhere to send commands from outside the network, as if from a connection
+    pub fn command_sender(&self) -> Sender<Command> {
+        self.command_sender.clone()
+    }
+
+    ///An id no connection has or will have, for a player who isn't connected over the network
+    pub fn new_id(&self) -> i32 {
+        self.next_id.fetch_add(1, Ordering::SeqCst) as i32
     }
 }
 
@@ -218,4 +867,141 @@ impl CommandProducer for GameServer {
             Err(TryRecvError::Empty) => Err(GetCommandErr::NoCommands),
         }
     }
+
+    fn wait_for_command(&self, timeout: Option<Duration>) -> Result<Command,GetCommandErr> {
+        let command = match timeout {
+            Some(timeout) => self.command_receiver.recv_timeout(timeout),
+            None => self.command_receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match command {
+            Ok(command) => Ok(command),
+            Err(RecvTimeoutError::Disconnected) => Err(GetCommandErr::Failed("Disconnected".to_string())),
+            Err(RecvTimeoutError::Timeout) => Err(GetCommandErr::NoCommands),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::board::Colour;
+
+    fn messages(buffer: &mut MessageBuffer) -> Vec<Result<String,()>> {
+        let mut messages = Vec::new();
+        while let Some(message) = buffer.next_message() {
+            messages.push(message.map(|m| String::from_utf8(m).unwrap()));
+        }
+        messages
+    }
+
+    #[test]
+    fn splits_and_joins_messages() {
+        let mut buffer = MessageBuffer::new(b';');
+        buffer.push(b"Bring it, yo;\n  Gimmeh!;Where can");
+        assert_eq!(messages(&mut buffer), vec![Ok("Bring it, yo;".to_string()), Ok("Gimmeh!;".to_string())]);
+
+        buffer.push(b" I go?");
+        assert_eq!(messages(&mut buffer), vec![]);
+        buffer.push(b";\n");
+        assert_eq!(messages(&mut buffer), vec![Ok("Where can I go?;".to_string())]);
+    }
+
+    #[test]
+    fn skips_blank_lines_between_json_messages() {
+        let mut buffer = MessageBuffer::new(b'\n');
+        buffer.push(b"\n\n  {\"type\":\"ready\"}\n\r\n{\"type\":\"get_move\"}\n");
+        assert_eq!(messages(&mut buffer), vec![Ok("{\"type\":\"ready\"}\n".to_string()),
+                                               Ok("{\"type\":\"get_move\"}\n".to_string())]);
+    }
+
+    #[test]
+    fn throws_away_long_messages() {
+        let mut buffer = MessageBuffer::new(b';');
+        let long = vec![b'a'; MAX_MESSAGE_LENGTH + 1];
+
+        //All at once
+        buffer.push(&long);
+        buffer.push(b";Gimmeh!;");
+        assert_eq!(messages(&mut buffer), vec![Err(()), Ok("Gimmeh!;".to_string())]);
+
+        //And before the end of it has arrived
+        buffer.push(&long);
+        assert_eq!(messages(&mut buffer), vec![Err(())]);
+        buffer.push(&long);
+        assert_eq!(messages(&mut buffer), vec![]);
+        buffer.push(b"aaa;Gimmeh!;");
+        assert_eq!(messages(&mut buffer), vec![Ok("Gimmeh!;".to_string())]);
+    }
+
+    //Answers the next command the way the engine would, checking it's the one expected
+    fn answer(server: &GameServer, expected: &str, response: CommandResponse) {
+        let command = server.wait_for_command(Some(Duration::from_secs(5))).ok().expect("no command");
+        assert_eq!(format!("{:?}", command.data), expected);
+        //Nobody waits for the answer to Leave
+        let _ = command.reply.send(response);
+    }
+
+    #[test]
+    fn talks_over_websockets() {
+        let localhost = "127.0.0.1:0".parse().unwrap();
+        let server = GameServer::new(localhost, Some(localhost)).unwrap();
+        let addr = server.websocket_addr().unwrap();
+        let stream = TcpStream::connect(addr).unwrap();
+        let (mut socket, _) = tungstenite::client(format!("ws://{}/", addr), stream).unwrap();
+
+        socket.send(Message::Text("Hello, my name is Bob;".to_string())).unwrap();
+        answer(&server, r#"Register("Bob", 1)"#, Ok(CommandOk::None));
+        assert_eq!(socket.read().unwrap(), Message::Text("Oh, hai Bob!;\n".to_string()));
+
+        //Two messages in one, and a reply sent while the client is waiting
+        socket.send(Message::Text("Bring it, yo;Gimmeh!;".to_string())).unwrap();
+        answer(&server, "Ready(1)", Ok(CommandOk::Ready("Alice".to_string(), Colour::White)));
+        assert_eq!(socket.read().unwrap(),
+                   Message::Text("You are White and Alice wants to batter you;\n".to_string()));
+        answer(&server, "GetMove(1)", Ok(CommandOk::Move(Move::new(0, 1, 2, 1))));
+        assert_eq!(socket.read().unwrap(), Message::Text("(0,1)->(2,1);\n".to_string()));
+
+        socket.close(None).unwrap();
+        answer(&server, "Leave(1)", Ok(CommandOk::None));
+    }
+
+    #[test]
+    fn speaks_json_over_websockets_without_newlines() {
+        let localhost = "127.0.0.1:0".parse().unwrap();
+        let server = GameServer::new(localhost, Some(localhost)).unwrap();
+        let addr = server.websocket_addr().unwrap();
+        let stream = TcpStream::connect(addr).unwrap();
+        let (mut socket, _) = tungstenite::client(format!("ws://{}/", addr), stream).unwrap();
+
+        socket.send(Message::Text(r#"{"type":"get_board"}"#.to_string())).unwrap();
+        answer(&server, "GetBoard(1)", Err(CommandErr::NotRegistered));
+        match socket.read().unwrap() {
+            Message::Text(reply) => assert!(reply.contains(r#""error":"not_registered""#), "{}", reply),
+            reply => panic!("{:?}", reply),
+        }
+    }
+
+    #[test]
+    fn answers_pings_and_closes_over_websockets() {
+        let localhost = "127.0.0.1:0".parse().unwrap();
+        let server = GameServer::new(localhost, Some(localhost)).unwrap();
+        let addr = server.websocket_addr().unwrap();
+        let stream = TcpStream::connect(addr).unwrap();
+        let (mut socket, _) = tungstenite::client(format!("ws://{}/", addr), stream).unwrap();
+
+        socket.send(Message::Ping(b"tick".to_vec())).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::Pong(b"tick".to_vec()));
+
+        //The server acknowledges the close, after which there's nothing more to read
+        socket.close(None).unwrap();
+        answer(&server, "Leave(1)", Ok(CommandOk::None));
+        loop {
+            match socket.read() {
+                Ok(Message::Close(_)) => continue,
+                Err(WsError::ConnectionClosed) => break,
+                other => panic!("{:?}", other),
+            }
+        }
+    }
 }
@@ -0,0 +1,197 @@
+//! Shows the game being played, or a recorded one, in the terminal the server was started from
+
+use std::io::{self,IsTerminal,Read,Write};
+use std::process::{Command,Stdio};
+
+use lines_of_action::board::Colour;
+use lines_of_action::clock;
+use lines_of_action::tui;
+use lines_of_action::replay::Replay;
+
+use super::{Engine,View};
+
+//What's on screen, so it's only redrawn when something has changed
+#[derive(PartialEq)]
+struct Shown {
+    moves: usize,
+    players: usize,
+    game_started: bool,
+    over: bool,
+    //Whole seconds left on each side's clock
+    clocks: Option<(u64,u64)>,
+}
+
+pub struct Terminal {
+    shown: Option<Shown>,
+}
+
+impl Terminal {
+    pub fn new() -> Terminal {
+        Terminal { shown: None }
+    }
+
+    fn draw(&self, engine: &Engine) -> io::Result<()> {
+        let mut out = String::from(tui::CLEAR_SCREEN);
+        out.push_str(&tui::board(&engine.board, engine.moves.last()));
+        out.push('\n');
+
+        for &colour in [Colour::Black, Colour::White].iter() {
+            let to_move = engine.game_started && engine.result.is_none() && engine.turn == colour;
+            //Sides aren't picked until both players are ready
+            let name = if engine.game_started { engine.player_name(colour) } else { "" };
+            let time = engine.clocks.as_ref()
+                .map(|clocks| clock::format_time(clocks.remaining(colour)))
+                .unwrap_or_default();
+            out.push_str(&format!("{} {:<20} {:>6} {}\n", tui::piece(colour), name, time,
+                                  if to_move { "<" } else { "" }));
+        }
+
+        out.push('\n');
+        out.push_str(&match engine.result {
+            Some(result) => format!("Game over, {}", result),
+            None if engine.game_started => format!("Move {}, {:?} to move", engine.moves.len() + 1, engine.turn),
+            None => format!("Waiting for players ({} of 2)", engine.players.len()),
+        });
+        out.push('\n');
+
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+impl View for Terminal {
+    fn update(&mut self, engine: &mut Engine) -> bool {
+        let shown = Shown {
+            moves: engine.moves.len(),
+            players: engine.players.len(),
+            game_started: engine.game_started,
+            over: engine.result.is_some(),
+            clocks: engine.clocks.as_ref().map(|clocks| {
+                (clocks.remaining(Colour::Black).as_secs(), clocks.remaining(Colour::White).as_secs())
+            }),
+        };
+
+        if self.shown.as_ref() != Some(&shown) {
+            if let Err(e) = self.draw(engine) {
+                error!("Couldn't draw the game in the terminal: {}", e);
+                return false;
+            }
+            self.shown = Some(shown);
+        }
+        true
+    }
+}
+
+//How many lines of moves to show when replaying
+const MOVE_LIST_LINES: usize = 10;
+
+//Puts the terminal into a mode where keys are read as soon as they're pressed, without being
+//shown, until dropped. Ctrl-C is read like any other key, so the terminal is always put back.
+struct RawMode {
+    //What `stty -g` said the settings were before
+    saved: String,
+}
+
+impl RawMode {
+    fn new() -> io::Result<RawMode> {
+        let saved = Command::new("stty").arg("-g")
+            .stdin(Stdio::inherit()).stderr(Stdio::inherit())
+            .output()?;
+        if !saved.status.success() {
+            return Err(io::Error::new(io::ErrorKind::Other, "stty couldn't read the terminal's settings"));
+        }
+        let saved = String::from_utf8_lossy(&saved.stdout).trim().to_string();
+        stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+        Ok(RawMode { saved: saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Err(e) = stty(&[&self.saved]) {
+            error!("Couldn't put the terminal back how it was: {}", e);
+        }
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<()> {
+    let status = Command::new("stty").args(args).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::Other, format!("stty {} failed", args.join(" "))))
+    }
+}
+
+fn draw_replay(replay: &Replay, number: &str) -> io::Result<()> {
+    let mut out = String::from(tui::CLEAR_SCREEN);
+    out.push_str(&tui::board(replay.board(), replay.last_move()));
+    out.push('\n');
+
+    let over = replay.current() == replay.move_count() && replay.record.result.is_some();
+    for &(colour, ref name) in [(Colour::Black, &replay.record.black), (Colour::White, &replay.record.white)].iter() {
+        let to_move = !over && replay.turn() == colour;
+        out.push_str(&format!("{} {:<20} {}\n", tui::piece(colour), name, if to_move { "<" } else { "" }));
+    }
+    out.push_str(&format!("\n{}\n\n", replay.status()));
+    out.push_str(&tui::move_list(replay.notation(), replay.current(), MOVE_LIST_LINES));
+    out.push_str("\nn, space or right steps forward, p or left back, s or up and e or down go to the start\n");
+    out.push_str("and end, a number followed by Enter goes to that move and q quits: ");
+    out.push_str(number);
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    stdout.write_all(out.as_bytes())?;
+    stdout.flush()
+}
+
+///Shows a recorded game in the terminal, acting on each key as it's pressed, until told to stop
+///or the input ends
+pub fn replay(mut replay: Replay) -> io::Result<()> {
+    //Input from elsewhere is read just the same, without touching the terminal
+    let _raw_mode = if io::stdin().is_terminal() { Some(RawMode::new()?) } else { None };
+    let stdin = io::stdin();
+    let mut keys = stdin.lock().bytes();
+    //The move number typed so far
+    let mut number = String::new();
+    loop {
+        draw_replay(&replay, &number)?;
+
+        let key = match keys.next() {
+            Some(key) => key?,
+            None => return Ok(()),
+        };
+        match key {
+            b'0'..=b'9' => number.push(key as char),
+            b'\n' | b'\r' if !number.is_empty() => {
+                replay.go_to(number.parse::<usize>().unwrap_or(usize::MAX));
+                number.clear();
+            },
+            //Backspace or delete
+            8 | 127 => { number.pop(); },
+            b'n' | b' ' | b'\n' | b'\r' => replay.forward(),
+            b'p' => replay.back(),
+            b's' => replay.to_start(),
+            b'e' => replay.to_end(),
+            //Ctrl-C or Ctrl-D
+            b'q' | 3 | 4 => return Ok(()),
+            //Arrow keys arrive as escape sequences, e.g. ESC [ C for right, or ESC O C in some modes
+            0x1b => {
+                match keys.next().transpose()? {
+                    Some(b'[') | Some(b'O') => {},
+                    _ => continue,
+                }
+                match keys.next().transpose()? {
+                    Some(b'C') => replay.forward(),
+                    Some(b'D') => replay.back(),
+                    Some(b'A') | Some(b'H') => replay.to_start(),
+                    Some(b'B') | Some(b'F') => replay.to_end(),
+                    _ => {},
+                }
+            },
+            _ => {},
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Colour themes for the window
+//!
+//! A theme file sets colours one to a line, as `NAME = RED,GREEN,BLUE[,ALPHA]` with each part
+//! from 0 to 255. Blank lines and lines starting with `#` are ignored, and colours which
+//! aren't set keep their default. For example:
+//!
+//! ```text
+//! # Green felt
+//! background = 20,90,40
+//! grid = 10,50,20
+//! last_move = 255,255,255,64
+//! ```
+//!
+//! The names are those of `Theme`'s fields. The default theme is written out in full by
+//! `Theme::default().to_string()`.
+
+use std::fmt::{Formatter,Error,Display};
+use std::str::FromStr;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+///A colour, with how opaque it is from 0 (not at all) to 255
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Rgba(pub u8, pub u8, pub u8, pub u8);
+
+impl Display for Rgba {
+    fn fmt(&self, f: &mut Formatter) -> Result<(),Error> {
+        match *self {
+            Rgba(r,g,b,255) => write!(f, "{},{},{}", r, g, b),
+            Rgba(r,g,b,a) => write!(f, "{},{},{},{}", r, g, b, a),
+        }
+    }
+}
+
+impl FromStr for Rgba {
+    type Err = ();
+
+    ///Parses `%red,%green,%blue[,%alpha]`
+    fn from_str(s: &str) -> Result<Rgba, ()> {
+        let parts = s.split(',')
+            .map(|part| part.trim().parse::<u8>())
+            .collect::<Result<Vec<_>,_>>()
+            .map_err(|_| ())?;
+        match parts[..] {
+            [r,g,b] => Ok(Rgba(r,g,b,255)),
+            [r,g,b,a] => Ok(Rgba(r,g,b,a)),
+            _ => Err(()),
+        }
+    }
+}
+
+///The colours the game is drawn in
+#[derive(Clone,Debug,PartialEq)]
+pub struct Theme {
+    ///Behind everything, including the cells
+    pub background: Rgba,
+    ///The lines between cells
+    pub grid: Rgba,
+    pub white_piece: Rgba,
+    pub black_piece: Rgba,
+    ///Coordinates and the writing under the board
+    pub text: Rgba,
+    ///Around the pieces under the board which show each side's colour
+    pub outline: Rgba,
+    ///Where the last move went from and to
+    pub last_move: Rgba,
+    ///The piece a person has picked up
+    pub selected: Rgba,
+    ///Where the piece a person has picked up can go
+    pub destination: Rgba,
+    ///Behind the name of the side to move
+    pub to_move: Rgba,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            background: Rgba(75,45,25,255),
+            grid: Rgba(0,0,0,255),
+            white_piece: Rgba(255,255,255,255),
+            black_piece: Rgba(0,0,0,255),
+            text: Rgba(255,255,255,255),
+            outline: Rgba(128,128,128,255),
+            last_move: Rgba(100,150,255,128),
+            selected: Rgba(255,255,0,128),
+            destination: Rgba(0,255,0,96),
+            to_move: Rgba(255,255,0,64),
+        }
+    }
+}
+
+impl Theme {
+    //Each colour with its name in theme files
+    fn colours_mut(&mut self) -> [(&'static str, &mut Rgba); 10] {
+        [("background", &mut self.background),
+         ("grid", &mut self.grid),
+         ("white_piece", &mut self.white_piece),
+         ("black_piece", &mut self.black_piece),
+         ("text", &mut self.text),
+         ("outline", &mut self.outline),
+         ("last_move", &mut self.last_move),
+         ("selected", &mut self.selected),
+         ("destination", &mut self.destination),
+         ("to_move", &mut self.to_move)]
+    }
+
+    ///Reads a theme file
+    pub fn load(path: &Path) -> Result<Theme,String> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        contents.parse::<Theme>().map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+impl Display for Theme {
+    ///Writes every colour in the theme file format
+    fn fmt(&self, f: &mut Formatter) -> Result<(),Error> {
+        for (name, colour) in self.clone().colours_mut().iter() {
+            writeln!(f, "{} = {}", name, colour)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    ///Parses a theme file, saying which line is wrong if one is
+    fn from_str(s: &str) -> Result<Theme, String> {
+        let mut theme = Theme::default();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap().trim();
+            let colour = match parts.next().map(|value| value.trim().parse::<Rgba>()) {
+                Some(Ok(colour)) => colour,
+                _ => return Err(format!("line {}: expected NAME = RED,GREEN,BLUE[,ALPHA]", i + 1)),
+            };
+
+            let mut colours = theme.colours_mut();
+            match colours.iter_mut().find(|&&mut (n, _)| n == name) {
+                Some(&mut (_, ref mut slot)) => **slot = colour,
+                None => return Err(format!("line {}: no colour called {}", i + 1, name)),
+            }
+        }
+        Ok(theme)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colours() {
+        assert_eq!("1,2,3".parse::<Rgba>(), Ok(Rgba(1,2,3,255)));
+        assert_eq!(" 1, 2 ,3,4 ".parse::<Rgba>(), Ok(Rgba(1,2,3,4)));
+        for colour in &[Rgba(0,0,0,255), Rgba(10,20,30,40)] {
+            assert_eq!(colour.to_string().parse::<Rgba>(), Ok(*colour));
+        }
+        for s in &["", "1,2", "1,2,3,4,5", "1,2,256", "1,2,-3", "red"] {
+            assert!(s.parse::<Rgba>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn default_theme_round_trips() {
+        assert_eq!(Theme::default().to_string().parse::<Theme>(), Ok(Theme::default()));
+    }
+
+    #[test]
+    fn unset_colours_keep_their_default() {
+        let theme = "# Green felt\n\n  background = 20,90,40\n\tlast_move=255,255,255,64\n"
+            .parse::<Theme>().unwrap();
+        assert_eq!(theme, Theme {
+            background: Rgba(20,90,40,255),
+            last_move: Rgba(255,255,255,64),
+            ..Theme::default()
+        });
+        assert_eq!("".parse::<Theme>(), Ok(Theme::default()));
+    }
+
+    #[test]
+    fn says_which_line_is_wrong() {
+        assert_eq!("grid = 0,0,0\n\nbackground 1,2,3".parse::<Theme>(),
+                   Err("line 3: expected NAME = RED,GREEN,BLUE[,ALPHA]".to_string()));
+        assert_eq!("grid = 0,0,300".parse::<Theme>(),
+                   Err("line 1: expected NAME = RED,GREEN,BLUE[,ALPHA]".to_string()));
+        assert_eq!("# comment\nsky = 0,0,255".parse::<Theme>(),
+                   Err("line 2: no colour called sky".to_string()));
+    }
+}
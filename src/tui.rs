@@ -0,0 +1,101 @@
+//! Drawing the game in a terminal with ANSI escape codes, for watching where there's no window
+
+use super::board::{Board,Colour,BOARD_SIZE};
+use super::command::Move;
+
+///Clears the terminal and moves the cursor to the top left
+pub const CLEAR_SCREEN: &'static str = "\x1b[2J\x1b[H";
+///Puts the terminal's colours back to normal
+pub const RESET: &'static str = "\x1b[0m";
+
+const LIGHT_CELL: &'static str = "\x1b[48;5;137m";
+const DARK_CELL: &'static str = "\x1b[48;5;94m";
+const MOVED_CELL: &'static str = "\x1b[48;5;67m";
+const WHITE_PIECE: &'static str = "\x1b[1;97m";
+const BLACK_PIECE: &'static str = "\x1b[1;30m";
+
+///A piece of the given colour
+pub fn piece(colour: Colour) -> String {
+    format!("{}\u{25cf}{}", match colour {
+        Colour::White => WHITE_PIECE,
+        Colour::Black => BLACK_PIECE,
+    }, RESET)
+}
+
+///Draws the board in colour, numbered along the top with x and down the side with y, with
+///the cells `last_move` went from and to picked out
+pub fn board(board: &Board, last_move: Option<&Move>) -> String {
+    let mut out = String::from(" ");
+    for x in 0..BOARD_SIZE {
+        out.push_str(&format!(" {}", x));
+    }
+    out.push('\n');
+
+    for y in 0..BOARD_SIZE {
+        out.push_str(&format!("{} ", y));
+        for x in 0..BOARD_SIZE {
+            let moved = last_move.map_or(false, |m| (m.sx,m.sy) == (x,y) || (m.dx,m.dy) == (x,y));
+            out.push_str(if moved {
+                MOVED_CELL
+            } else if (x + y) % 2 == 0 {
+                LIGHT_CELL
+            } else {
+                DARK_CELL
+            });
+
+            let cell = board.cells[x as usize][y as usize];
+            match (cell.has_piece, cell.colour) {
+                (false, _) => out.push_str("  "),
+                (true, Colour::White) => out.push_str(&format!("{}\u{25cf} ", WHITE_PIECE)),
+                (true, Colour::Black) => out.push_str(&format!("{}\u{25cf} ", BLACK_PIECE)),
+            }
+        }
+        out.push_str(RESET);
+        out.push('\n');
+    }
+    out
+}
+
+const CURRENT_MOVE: &'static str = "\x1b[7m";
+
+///Lists `moves` two to a line, as in a game record, picking out the last of the first
+///`current`. Only `lines` lines are shown, around that move.
+pub fn move_list(moves: &[String], current: usize, lines: usize) -> String {
+    let total = (moves.len() + 1) / 2;
+    let current_line = current.saturating_sub(1) / 2;
+    let first = current_line.saturating_sub(lines / 2).min(total.saturating_sub(lines));
+
+    let mut out = String::new();
+    for i in first..total.min(first + lines) {
+        out.push_str(&format!("{:>3}.", i + 1));
+        for index in 2*i..(2*i + 2).min(moves.len()) {
+            if index + 1 == current {
+                out.push_str(&format!(" {}{:<9}{}", CURRENT_MOVE, moves[index], RESET));
+            } else {
+                out.push_str(&format!(" {:<9}", moves[index]));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_the_board_and_last_move() {
+        let drawn = board(&Board::new(), Some(&Move::new(1,0,1,2)));
+        let lines = drawn.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[0], "  0 1 2 3 4 5 6 7");
+        assert!(lines[1].starts_with(&format!("0 {}  {}{}\u{25cf} {}{}\u{25cf} ",
+                                                LIGHT_CELL, MOVED_CELL, BLACK_PIECE, LIGHT_CELL, BLACK_PIECE)));
+        assert!(lines[2].starts_with(&format!("1 {}{}\u{25cf} {}  ", DARK_CELL, WHITE_PIECE, LIGHT_CELL)));
+        assert!(lines.iter().skip(1).all(|line| line.ends_with(RESET)));
+
+        assert_eq!(drawn.matches(MOVED_CELL).count(), 2);
+        assert_eq!(drawn.matches('\u{25cf}').count(), 24);
+    }
+}